@@ -0,0 +1,74 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use payment_engine::accounts::{Accounts, AccountsKind};
+use payment_engine::generate::{Generator, GeneratorConfig};
+use payment_engine::{ClientId, Engine, EngineConfig};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use rust_decimal::Decimal;
+
+const LOOKUPS: usize = 1_000_000;
+const ROWS: usize = 500_000;
+
+const KINDS: [(&str, AccountsKind); 2] = [
+    ("hashmap", AccountsKind::HashMap),
+    ("dense", AccountsKind::Dense),
+];
+
+// Looks up the account of a random client for every row, creating it on first use as the engine does.
+fn lookups(c: &mut Criterion) {
+    let mut rng = ChaCha8Rng::seed_from_u64(0);
+    let clients: Vec<ClientId> = (0..LOOKUPS)
+        .map(|_| rng.gen_range(0..=u16::MAX as ClientId))
+        .collect();
+    let mut group = c.benchmark_group("get_or_insert");
+    group.throughput(Throughput::Elements(LOOKUPS as u64));
+    for (name, kind) in KINDS {
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                || Accounts::<Decimal>::new(kind),
+                |mut accounts| {
+                    for client in &clients {
+                        accounts.get_or_insert(*client).locked();
+                    }
+                    accounts
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn process_records(c: &mut Criterion) {
+    let mut group = c.benchmark_group("process_records");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(ROWS as u64));
+    for clients in [100, u16::MAX as ClientId] {
+        let mut input = Vec::new();
+        Generator::new(GeneratorConfig {
+            clients,
+            rows: ROWS,
+            ..GeneratorConfig::default()
+        })
+        .write_csv(&mut input)
+        .unwrap();
+        for (name, kind) in KINDS {
+            group.bench_with_input(BenchmarkId::new(name, clients), &input, |b, input| {
+                b.iter_batched(
+                    || {
+                        Engine::new(EngineConfig {
+                            accounts: kind,
+                            ..EngineConfig::default()
+                        })
+                    },
+                    |mut engine| engine.process_records(input.as_slice()).unwrap(),
+                    BatchSize::LargeInput,
+                )
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, lookups, process_records);
+criterion_main!(benches);
@@ -0,0 +1,82 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use payment_engine::amount::{Amount, Fixed};
+use payment_engine::generate::{Generator, GeneratorConfig};
+use payment_engine::{Engine, EngineConfig};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use rust_decimal::Decimal;
+
+const AMOUNTS: usize = 1_000_000;
+const ROWS: usize = 200_000;
+
+// Amounts with four decimal places, as the engine holds them.
+fn amounts<A: Amount>() -> Vec<A> {
+    let mut rng = ChaCha8Rng::seed_from_u64(0);
+    (0..AMOUNTS)
+        .map(|_| {
+            let amount = Decimal::new(rng.gen_range(1..100_000_000), 4);
+            A::from_decimal(amount).unwrap()
+        })
+        .collect()
+}
+
+// Adds and subtracts every amount with overflow checks, as the engine updates balances.
+fn checked_arithmetic<A: Amount>(amounts: &[A]) -> A {
+    let mut balance = A::ZERO;
+    for (i, amount) in amounts.iter().enumerate() {
+        balance = if i % 3 == 0 {
+            balance.checked_sub(*amount).unwrap()
+        } else {
+            balance.checked_add(*amount).unwrap()
+        };
+    }
+    balance
+}
+
+fn process<A: Amount>(input: &[u8]) {
+    let mut engine = Engine::<A>::with_amount(EngineConfig::default());
+    engine.process_records(input).unwrap();
+}
+
+fn amount(c: &mut Criterion) {
+    let mut group = c.benchmark_group("checked_arithmetic");
+    group.throughput(Throughput::Elements(AMOUNTS as u64));
+    let decimals = amounts::<Decimal>();
+    group.bench_function("decimal", |b| b.iter(|| checked_arithmetic(&decimals)));
+    let fixed = amounts::<Fixed>();
+    group.bench_function("fixed", |b| b.iter(|| checked_arithmetic(&fixed)));
+    group.finish();
+
+    let mut input = Vec::new();
+    Generator::new(GeneratorConfig {
+        rows: ROWS,
+        ..GeneratorConfig::default()
+    })
+    .write_csv(&mut input)
+    .unwrap();
+    let mut group = c.benchmark_group("process_records");
+    group.sample_size(20);
+    group.throughput(Throughput::Elements(ROWS as u64));
+    group.bench_with_input(
+        BenchmarkId::from_parameter("decimal"),
+        &input,
+        |b, input| {
+            b.iter_batched(
+                || input,
+                |input| process::<Decimal>(input),
+                BatchSize::LargeInput,
+            )
+        },
+    );
+    group.bench_with_input(BenchmarkId::from_parameter("fixed"), &input, |b, input| {
+        b.iter_batched(
+            || input,
+            |input| process::<Fixed>(input),
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, amount);
+criterion_main!(benches);
@@ -0,0 +1,112 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use payment_engine::generate::{Generator, GeneratorConfig};
+use payment_engine::reader::TransactionReader;
+use payment_engine::{ClientId, Engine, EngineConfig, Transaction, TransactionType, TxId};
+use rust_decimal::Decimal;
+use std::str::FromStr;
+
+const ROWS: usize = 200_000;
+
+fn generate(whitespace_rate: f64, batches: bool) -> Vec<u8> {
+    let mut transactions = Vec::new();
+    Generator::new(GeneratorConfig {
+        rows: ROWS,
+        whitespace_rate,
+        ..GeneratorConfig::default()
+    })
+    .write_csv(&mut transactions)
+    .unwrap();
+    if !batches {
+        return transactions;
+    }
+    // Adds a batch column with batches of 10 rows.
+    let text = String::from_utf8(transactions).unwrap();
+    let mut with_batches = String::new();
+    for (row, line) in text.lines().enumerate() {
+        if row == 0 {
+            with_batches.push_str(&format!("{},batch\n", line));
+        } else {
+            with_batches.push_str(&format!("{},b{}\n", line, (row - 1) / 10));
+        }
+    }
+    with_batches.into_bytes()
+}
+
+// The parsing loop the engine used before `TransactionReader`: every field is copied into a new string, which the type and the numbers are parsed from.
+fn parse_string_records(input: &[u8]) -> usize {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(input);
+    reader.headers().unwrap();
+    let mut records = csv::StringRecord::new();
+    let mut rows = 0;
+    while reader.read_record(&mut records).unwrap() {
+        let mut row_trimmed = Vec::new();
+        for fields in &records {
+            let mut fields_ = fields.to_string();
+            fields_.retain(|c| !c.is_whitespace());
+            row_trimmed.push(fields_);
+        }
+        let transaction = Transaction::new(
+            TransactionType::from_bytes(row_trimmed[0].as_bytes()),
+            row_trimmed[1].parse::<ClientId>().unwrap(),
+            row_trimmed[2].parse::<TxId>().unwrap(),
+            Decimal::from_str(row_trimmed[3].as_str()).ok(),
+        );
+        criterion::black_box(&transaction);
+        rows += 1;
+    }
+    rows
+}
+
+fn parse_transaction_reader(input: &[u8]) -> usize {
+    let mut reader = TransactionReader::new(input).unwrap();
+    let mut rows = 0;
+    while let Some(row) = reader.next_row().unwrap() {
+        criterion::black_box(row);
+        rows += 1;
+    }
+    rows
+}
+
+// Throughput of parsing alone, and of the whole pipeline from CSV bytes to accounts.
+fn parsing(c: &mut Criterion) {
+    let inputs = [
+        ("clean", generate(0.0, false)),
+        ("whitespace", generate(0.5, false)),
+        ("batches", generate(0.0, true)),
+    ];
+    let mut group = c.benchmark_group("parse");
+    group.sample_size(20);
+    group.throughput(Throughput::Elements(ROWS as u64));
+    for (name, input) in &inputs[..2] {
+        group.bench_with_input(
+            BenchmarkId::new("string_record", name),
+            input,
+            |b, input| b.iter(|| parse_string_records(input)),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("transaction_reader", name),
+            input,
+            |b, input| b.iter(|| parse_transaction_reader(input)),
+        );
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("process_records");
+    group.sample_size(20);
+    group.throughput(Throughput::Elements(ROWS as u64));
+    for (name, input) in &inputs {
+        group.bench_with_input(BenchmarkId::from_parameter(name), input, |b, input| {
+            b.iter_batched(
+                || Engine::new(EngineConfig::default()),
+                |mut engine| engine.process_records(input.as_slice()).unwrap(),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parsing);
+criterion_main!(benches);
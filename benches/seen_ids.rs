@@ -0,0 +1,101 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use payment_engine::generate::{Generator, GeneratorConfig};
+use payment_engine::seen::{SeenIds, SeenIdsKind};
+use payment_engine::{Engine, EngineConfig, TxId};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+const IDS: usize = 1_000_000;
+
+// Sequential IDs, as handed out by most upstream systems, and IDs spread over the whole ID space.
+fn id_patterns() -> Vec<(&'static str, Vec<TxId>)> {
+    let mut rng = ChaCha8Rng::seed_from_u64(0);
+    vec![
+        ("sequential", (1..=IDS as TxId).collect()),
+        ("sparse", (0..IDS).map(|_| rng.gen()).collect()),
+    ]
+}
+
+fn fill(kind: SeenIdsKind, ids: &[TxId]) -> Box<dyn SeenIds> {
+    let mut seen = kind.build();
+    for &tx in ids {
+        seen.insert(tx);
+    }
+    seen
+}
+
+fn seen_ids(c: &mut Criterion) {
+    let kinds = [
+        ("hashset", SeenIdsKind::HashSet),
+        ("roaring", SeenIdsKind::Roaring),
+    ];
+    let patterns = id_patterns();
+    // Criterion only measures time, so the memory used by each structure is printed once up front.
+    for (pattern, ids) in &patterns {
+        for (name, kind) in kinds {
+            let seen = fill(kind, ids);
+            println!(
+                "memory {}/{}: {} bytes for {} IDs ({:.2} bytes per ID)",
+                name,
+                pattern,
+                seen.heap_size(),
+                seen.len(),
+                seen.heap_size() as f64 / seen.len() as f64
+            );
+        }
+    }
+
+    let mut group = c.benchmark_group("insert");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(IDS as u64));
+    for (pattern, ids) in &patterns {
+        for (name, kind) in kinds {
+            group.bench_with_input(BenchmarkId::new(name, pattern), ids, |b, ids| {
+                b.iter(|| fill(kind, ids))
+            });
+        }
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("contains");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(IDS as u64));
+    for (pattern, ids) in &patterns {
+        for (name, kind) in kinds {
+            let seen = fill(kind, ids);
+            group.bench_with_input(BenchmarkId::new(name, pattern), ids, |b, ids| {
+                b.iter(|| ids.iter().filter(|&&tx| seen.contains(tx)).count())
+            });
+        }
+    }
+    group.finish();
+
+    let mut transactions = Vec::new();
+    Generator::new(GeneratorConfig {
+        rows: 100_000,
+        ..GeneratorConfig::default()
+    })
+    .write_csv(&mut transactions)
+    .unwrap();
+    let mut group = c.benchmark_group("process_records");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(100_000));
+    for (name, kind) in kinds {
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                || {
+                    Engine::new(EngineConfig {
+                        seen_ids: kind,
+                        ..EngineConfig::default()
+                    })
+                },
+                |mut engine| engine.process_records(transactions.as_slice()).unwrap(),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, seen_ids);
+criterion_main!(benches);
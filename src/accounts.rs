@@ -0,0 +1,303 @@
+use crate::amount::Amount;
+use crate::{Account, ClientId};
+use rust_decimal::Decimal;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::ops::Index;
+
+// How the engine stores the accounts.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum AccountsKind {
+    #[default]
+    HashMap,
+    // A table indexed by client ID. Every row finds its account without hashing.
+    Dense,
+}
+
+// The accounts of the engine by client ID. Only clients that have appeared in the input have an account.
+#[derive(Clone, Debug)]
+pub struct Accounts<A = Decimal> {
+    storage: Storage<A>,
+}
+
+#[derive(Clone, Debug)]
+enum Storage<A> {
+    Map(HashMap<ClientId, Account<A>>),
+    // Slot `client` holds the account of the client. The table grows up to the highest client seen,
+    // so it never has more than 65,536 slots and small client IDs take little memory.
+    // Higher client IDs, which only the `wide-ids` feature allows, are kept in `overflow`.
+    Dense {
+        slots: Vec<Option<Account<A>>>,
+        len: usize,
+        overflow: BTreeMap<ClientId, Account<A>>,
+    },
+}
+
+// Number of slots of the dense table.
+const DENSE_SLOTS: usize = 1 << 16;
+
+// Slot of the client in the dense table, or None if its account is kept in the overflow map.
+#[allow(clippy::unnecessary_fallible_conversions)] // Infallible without `wide-ids`.
+fn slot(client: ClientId) -> Option<usize> {
+    usize::try_from(client)
+        .ok()
+        .filter(|&index| index < DENSE_SLOTS)
+}
+
+impl<A: Amount> Accounts<A> {
+    pub fn new(kind: AccountsKind) -> Self {
+        let storage = match kind {
+            AccountsKind::HashMap => Storage::Map(HashMap::new()),
+            AccountsKind::Dense => Storage::Dense {
+                slots: Vec::new(),
+                len: 0,
+                overflow: BTreeMap::new(),
+            },
+        };
+        Self { storage }
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.storage {
+            Storage::Map(map) => map.len(),
+            Storage::Dense { len, overflow, .. } => len + overflow.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, client: &ClientId) -> Option<&Account<A>> {
+        match &self.storage {
+            Storage::Map(map) => map.get(client),
+            Storage::Dense {
+                slots, overflow, ..
+            } => match slot(*client) {
+                Some(index) => slots.get(index)?.as_ref(),
+                None => overflow.get(client),
+            },
+        }
+    }
+
+    pub fn get_mut(&mut self, client: &ClientId) -> Option<&mut Account<A>> {
+        match &mut self.storage {
+            Storage::Map(map) => map.get_mut(client),
+            Storage::Dense {
+                slots, overflow, ..
+            } => match slot(*client) {
+                Some(index) => slots.get_mut(index)?.as_mut(),
+                None => overflow.get_mut(client),
+            },
+        }
+    }
+
+    pub fn contains_key(&self, client: &ClientId) -> bool {
+        self.get(client).is_some()
+    }
+
+    // Returns the account of the client, creating an empty one if the client has not appeared before.
+    pub fn get_or_insert(&mut self, client: ClientId) -> &mut Account<A> {
+        match &mut self.storage {
+            Storage::Map(map) => map.entry(client).or_insert_with(|| Account::new(client)),
+            Storage::Dense {
+                slots,
+                len,
+                overflow,
+            } => {
+                let index = match slot(client) {
+                    Some(index) => index,
+                    None => {
+                        return overflow
+                            .entry(client)
+                            .or_insert_with(|| Account::new(client))
+                    }
+                };
+                if slots.len() <= index {
+                    slots.resize_with(index + 1, || None);
+                }
+                let slot = &mut slots[index];
+                if slot.is_none() {
+                    *len += 1;
+                }
+                slot.get_or_insert_with(|| Account::new(client))
+            }
+        }
+    }
+
+    pub fn insert(&mut self, client: ClientId, account: Account<A>) -> Option<Account<A>> {
+        if let Storage::Map(map) = &mut self.storage {
+            return map.insert(client, account);
+        }
+        let existed = self.contains_key(&client);
+        let previous = std::mem::replace(self.get_or_insert(client), account);
+        existed.then_some(previous)
+    }
+
+    pub fn remove(&mut self, client: &ClientId) -> Option<Account<A>> {
+        match &mut self.storage {
+            Storage::Map(map) => map.remove(client),
+            Storage::Dense {
+                slots,
+                len,
+                overflow,
+            } => {
+                let index = match slot(*client) {
+                    Some(index) => index,
+                    None => return overflow.remove(client),
+                };
+                let removed = slots.get_mut(index)?.take();
+                if removed.is_some() {
+                    *len -= 1;
+                }
+                removed
+            }
+        }
+    }
+
+    // Accounts with their client ID. The dense table yields them ordered by client.
+    pub fn iter(&self) -> Box<dyn Iterator<Item = (&ClientId, &Account<A>)> + '_> {
+        match &self.storage {
+            Storage::Map(map) => Box::new(map.iter()),
+            Storage::Dense {
+                slots, overflow, ..
+            } => Box::new(
+                slots
+                    .iter()
+                    .flatten()
+                    .chain(overflow.values())
+                    .map(|account| (&account.client, account)),
+            ),
+        }
+    }
+
+    pub fn iter_mut(&mut self) -> Box<dyn Iterator<Item = (ClientId, &mut Account<A>)> + '_> {
+        match &mut self.storage {
+            Storage::Map(map) => {
+                Box::new(map.iter_mut().map(|(client, account)| (*client, account)))
+            }
+            Storage::Dense {
+                slots, overflow, ..
+            } => Box::new(
+                slots
+                    .iter_mut()
+                    .flatten()
+                    .chain(overflow.values_mut())
+                    .map(|account| (account.client, account)),
+            ),
+        }
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &ClientId> {
+        self.iter().map(|(client, _)| client)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Account<A>> {
+        self.iter().map(|(_, account)| account)
+    }
+
+    pub fn into_map(self) -> HashMap<ClientId, Account<A>> {
+        match self.storage {
+            Storage::Map(map) => map,
+            Storage::Dense {
+                slots, overflow, ..
+            } => slots
+                .into_iter()
+                .flatten()
+                .chain(overflow.into_values())
+                .map(|account| (account.client, account))
+                .collect(),
+        }
+    }
+}
+
+impl<A: Amount> Index<&ClientId> for Accounts<A> {
+    type Output = Account<A>;
+
+    fn index(&self, client: &ClientId) -> &Account<A> {
+        self.get(client).expect("no account for client")
+    }
+}
+
+// Accounts are equal if they have the same clients with the same accounts, however they are stored.
+impl<A: Amount> PartialEq for Accounts<A> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self
+                .iter()
+                .all(|(client, account)| other.get(client) == Some(account))
+    }
+}
+
+impl<A: Amount> Eq for Accounts<A> {}
+
+impl<A: Amount> PartialEq<HashMap<ClientId, Account<A>>> for Accounts<A> {
+    fn eq(&self, other: &HashMap<ClientId, Account<A>>) -> bool {
+        self.len() == other.len()
+            && self
+                .iter()
+                .all(|(client, account)| other.get(client) == Some(account))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate::{Generator, GeneratorConfig};
+    use crate::history::HistoryFilter;
+    use crate::{Engine, EngineConfig, EngineError};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_dense_accounts() -> Result<(), EngineError> {
+        let mut generated = Vec::new();
+        Generator::new(GeneratorConfig {
+            seed: 7,
+            clients: ClientId::MAX,
+            dispute_rate: 0.1,
+            close_rate: 0.1,
+            ..GeneratorConfig::default()
+        })
+        .write_csv(&mut generated)?;
+        for input in [
+            std::fs::read("test_process_records.csv")?,
+            std::fs::read("test_batches.csv")?,
+            std::fs::read("test_simulate.csv")?,
+            generated,
+        ] {
+            let mut outcomes = Vec::new();
+            for accounts in [AccountsKind::HashMap, AccountsKind::Dense] {
+                let mut engine = Engine::new(EngineConfig {
+                    accounts,
+                    history: true,
+                    ..EngineConfig::default()
+                });
+                engine.process_records(input.as_slice())?;
+                let results: Vec<_> = engine
+                    .query(&HistoryFilter::default())
+                    .into_iter()
+                    .cloned()
+                    .collect();
+                outcomes.push((engine.into_accounts(), results));
+            }
+            assert_eq!(outcomes[0], outcomes[1]);
+        }
+
+        let mut accounts = Accounts::<Decimal>::new(AccountsKind::Dense);
+        accounts.get_or_insert(ClientId::MAX);
+        accounts.get_or_insert(3);
+        accounts.get_or_insert(3);
+        assert_eq!(accounts.len(), 2);
+        assert_eq!(
+            accounts.keys().copied().collect::<Vec<_>>(),
+            vec![3, ClientId::MAX]
+        );
+        assert!(accounts.insert(4, Account::new(4)).is_none());
+        assert!(accounts.insert(4, Account::new(4)).is_some());
+        assert!(accounts.remove(&3).is_some());
+        assert!(accounts.remove(&3).is_none());
+        assert!(!accounts.contains_key(&0));
+        assert_eq!(accounts.len(), 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,319 @@
+use rust_decimal::Decimal;
+use serde::de::{self, Deserialize, Deserializer};
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Sub, SubAssign};
+
+// Number type the balances of an account are kept in. Amounts are read from the input as `Decimal` and converted once.
+pub trait Amount:
+    Copy
+    + Ord
+    + fmt::Debug
+    + fmt::Display
+    + Serialize
+    + Sum
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + AddAssign
+    + SubAssign
+    + 'static
+{
+    const ZERO: Self;
+    // Largest number of decimal places an amount can have.
+    const MAX_SCALE: u32;
+
+    // Returns None if the result cannot be represented exactly enough.
+    fn checked_add(self, other: Self) -> Option<Self>;
+    fn checked_sub(self, other: Self) -> Option<Self>;
+    // Converts an amount that has been rounded to at most `MAX_SCALE` places. Returns None if it is out of range.
+    fn from_decimal(amount: Decimal) -> Option<Self>;
+    fn to_decimal(self) -> Decimal;
+}
+
+impl Amount for Decimal {
+    const ZERO: Self = Decimal::ZERO;
+    const MAX_SCALE: u32 = 28;
+
+    // Only fails once the integer part no longer fits. Decimal places are rounded away before that.
+    fn checked_add(self, other: Self) -> Option<Self> {
+        Decimal::checked_add(self, other)
+    }
+
+    fn checked_sub(self, other: Self) -> Option<Self> {
+        Decimal::checked_sub(self, other)
+    }
+
+    fn from_decimal(amount: Decimal) -> Option<Self> {
+        Some(amount)
+    }
+
+    fn to_decimal(self) -> Decimal {
+        self
+    }
+}
+
+// Number of decimal places of a fixed-point amount.
+const SCALE: u32 = 4;
+// Largest mantissa of a `Decimal`.
+const MAX_MANTISSA: u128 = (1 << 96) - 1;
+// Largest number of ten-thousandths: the largest `Decimal`, so that both types accept the same amounts.
+const MAX_UNITS: i128 = MAX_MANTISSA as i128 * 10_000;
+
+// Amount held as an integer number of ten-thousandths. Additions and subtractions are exact:
+// a result that does not fit is reported as an overflow, where `Decimal` would round off decimal places.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Debug)]
+pub struct Fixed {
+    units: i128,
+}
+
+impl Fixed {
+    fn checked(units: i128) -> Option<Self> {
+        if units.abs() > MAX_UNITS {
+            return None;
+        }
+        Some(Self { units })
+    }
+
+    pub fn units(self) -> i128 {
+        self.units
+    }
+}
+
+impl Amount for Fixed {
+    const ZERO: Self = Fixed { units: 0 };
+    const MAX_SCALE: u32 = SCALE;
+
+    fn checked_add(self, other: Self) -> Option<Self> {
+        Self::checked(self.units + other.units)
+    }
+
+    fn checked_sub(self, other: Self) -> Option<Self> {
+        Self::checked(self.units - other.units)
+    }
+
+    fn from_decimal(mut amount: Decimal) -> Option<Self> {
+        if amount.scale() > SCALE {
+            amount.rescale(SCALE);
+        }
+        let units = amount
+            .mantissa()
+            .checked_mul(10_i128.pow(SCALE - amount.scale()))?;
+        Self::checked(units)
+    }
+
+    // Builds the `Decimal` from its parts, which is cheaper than `Decimal::from_i128_with_scale`.
+    // Amounts whose ten-thousandths do not fit in 96 bits keep as many decimal places as fit,
+    // rounded half away from zero like `Decimal::rescale`.
+    fn to_decimal(self) -> Decimal {
+        let units = self.units.unsigned_abs();
+        let mut mantissa = units;
+        let mut scale = SCALE;
+        while mantissa > MAX_MANTISSA {
+            scale -= 1;
+            let divisor = 10_u128.pow(SCALE - scale);
+            mantissa = units / divisor;
+            if units % divisor * 2 >= divisor {
+                mantissa += 1;
+            }
+        }
+        Decimal::from_parts(
+            mantissa as u32,
+            (mantissa >> 32) as u32,
+            (mantissa >> 64) as u32,
+            self.units < 0,
+            scale,
+        )
+    }
+}
+
+// Panics on overflow, like the operators of `Decimal`. The engine only uses them on amounts bounded by a balance.
+impl Add for Fixed {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        self.checked_add(other).expect("Addition overflowed")
+    }
+}
+
+impl Sub for Fixed {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        self.checked_sub(other).expect("Subtraction overflowed")
+    }
+}
+
+impl AddAssign for Fixed {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
+
+impl SubAssign for Fixed {
+    fn sub_assign(&mut self, other: Self) {
+        *self = *self - other;
+    }
+}
+
+impl Sum for Fixed {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::ZERO, Add::add)
+    }
+}
+
+impl fmt::Display for Fixed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.to_decimal(), f)
+    }
+}
+
+impl Serialize for Fixed {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Serialize::serialize(&self.to_decimal(), serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Fixed {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let amount = <Decimal as Deserialize>::deserialize(deserializer)?;
+        Self::from_decimal(amount)
+            .ok_or_else(|| de::Error::custom(format!("amount {} is out of range", amount)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::AuditMode;
+    use crate::generate::{Generator, GeneratorConfig};
+    use crate::history::{HistoryFilter, HistoryRecord};
+    use crate::replay::write_state;
+    use crate::{Engine, EngineConfig, EngineError, Settlement};
+    use pretty_assertions::assert_eq;
+    use rust_decimal_macros::dec;
+
+    // Account output, history and state dump of a run, or the error it stopped with.
+    type Outcome = Result<(Vec<String>, Vec<HistoryRecord>, String), String>;
+
+    fn run<A: Amount>(input: &[u8], config: &EngineConfig) -> Outcome {
+        let run = || -> Result<_, EngineError> {
+            let mut engine = Engine::<A>::with_amount(config.clone());
+            engine.process_records(input)?;
+            engine.verify_ledger()?;
+            engine.audit()?;
+            let mut accounts = Vec::new();
+            engine.write_accounts(&mut accounts)?;
+            // Accounts are written in the order of the hash map.
+            let mut accounts: Vec<_> = String::from_utf8(accounts)
+                .unwrap()
+                .lines()
+                .map(str::to_string)
+                .collect();
+            accounts[1..].sort_unstable();
+            let mut state = Vec::new();
+            write_state(engine.accounts(), &engine.output_precision(), &mut state)?;
+            let history = engine
+                .query(&HistoryFilter::default())
+                .into_iter()
+                .cloned()
+                .collect();
+            Ok((accounts, history, String::from_utf8(state).unwrap()))
+        };
+        run().map_err(|error| error.to_string())
+    }
+
+    #[test]
+    fn test_fixed_matches_decimal() -> Result<(), EngineError> {
+        let mut inputs = Vec::new();
+        for entry in std::fs::read_dir(".")? {
+            let path = entry?.path();
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            if name.starts_with("test_") && name.ends_with(".csv") {
+                inputs.push(std::fs::read(path)?);
+            }
+        }
+        for seed in 0..4 {
+            let mut generated = Vec::new();
+            Generator::new(GeneratorConfig {
+                seed,
+                dispute_rate: 0.1,
+                close_rate: 0.1,
+                malformed_rate: 0.05,
+                ..GeneratorConfig::default()
+            })
+            .write_csv(&mut generated)?;
+            inputs.push(generated);
+        }
+        // Amounts whose ten-thousandths need more than 96 bits, up to the largest amount of both types.
+        // Sums Decimal would round are left to `test_fixed_overflow`. The ledger sums the amounts of every client, so the largest amount gets an input of its own.
+        inputs.push(
+            b"type,client,tx,amount\n\
+              deposit,1,1,10000000000000000000000000\n\
+              deposit,1,2,0.5\n\
+              withdrawal,1,3,1000000000000000000000000.25\n\
+              dispute,1,1,\n"
+                .to_vec(),
+        );
+        inputs.push(
+            b"type,client,tx,amount\n\
+              deposit,1,1,79228162514264337593543950335\n\
+              dispute,1,1,\n\
+              resolve,1,1,\n"
+                .to_vec(),
+        );
+        let configs = [
+            EngineConfig {
+                history: true,
+                ..EngineConfig::default()
+            },
+            EngineConfig {
+                unlock_on_representment: true,
+                auth_expiry: Some(2),
+                settlement: Settlement::Delayed(2),
+                ledger: true,
+                history: true,
+                audit: Some(AuditMode::EveryTransaction),
+                ..EngineConfig::default()
+            },
+        ];
+        for input in &inputs {
+            for config in &configs {
+                assert_eq!(run::<Fixed>(input, config), run::<Decimal>(input, config));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_fixed_overflow() {
+        let max = Fixed::from_decimal(Decimal::MAX).unwrap();
+        let unit = Fixed::from_decimal(dec!(0.0001)).unwrap();
+        assert_eq!(max.to_decimal(), Decimal::MAX);
+        assert_eq!(max.checked_add(unit), None);
+        assert_eq!(
+            max.checked_sub(unit).map(Fixed::units),
+            Some(max.units() - 1)
+        );
+        // Decimal rounds the sum to fit, the fixed-point amount keeps it exact.
+        let large = Fixed::from_decimal(dec!(7922816251426433759354395.0335)).unwrap();
+        assert_eq!(
+            Amount::checked_add(large.to_decimal(), unit.to_decimal()),
+            Some(dec!(7922816251426433759354395.034))
+        );
+        assert_eq!(
+            large.checked_add(unit).map(Fixed::units),
+            Some(large.units() + 1)
+        );
+        assert_eq!(
+            (large + unit).to_decimal(),
+            dec!(7922816251426433759354395.034)
+        );
+        assert_eq!(Fixed::ZERO.to_string(), "0.0000");
+        assert_eq!((unit - unit).to_string(), "0.0000");
+        assert_eq!(
+            Fixed::from_decimal(dec!(-1.5)).unwrap().to_string(),
+            "-1.5000"
+        );
+    }
+}
@@ -0,0 +1,294 @@
+use crate::amount::Amount;
+use crate::{
+    Account, AuthorizationState, Balances, ClientId, DepositTotals, Rejection, Transaction,
+    TransactionType, TxId,
+};
+use rust_decimal::Decimal;
+use std::fmt;
+use thiserror::Error;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AuditMode {
+    // Check the account touched by every row and engine event, so that the first violating transaction is known.
+    EveryTransaction,
+    // Check every account once after processing.
+    End,
+}
+
+#[derive(Error, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Invariant {
+    #[error("held is negative")]
+    NegativeHeld,
+    #[error("pending is negative")]
+    NegativePending,
+    #[error("total is not the sum of available, held and pending")]
+    Total,
+    #[error("held is not the sum of the disputed deposits and open authorizations")]
+    Held,
+    #[error("pending is not the sum of the pending deposits")]
+    Pending,
+    #[error("the disputed total is not the sum of the disputed deposits")]
+    DisputedTotal,
+    #[error("the pending total is not the sum of the pending deposits")]
+    PendingTotal,
+    #[error("the chargebacked count is not the number of chargebacked deposits")]
+    ChargebackedCount,
+}
+
+impl Invariant {
+    // Returns the first invariant the account breaks with the actual and the expected value.
+    pub fn check<A: Amount>(account: &Account<A>) -> Option<(Invariant, Decimal, Decimal)> {
+        Self::check_amounts(account).map(|(invariant, actual, expected)| {
+            (invariant, actual.to_decimal(), expected.to_decimal())
+        })
+    }
+
+    fn check_amounts<A: Amount>(account: &Account<A>) -> Option<(Invariant, A, A)> {
+        if account.held < A::ZERO {
+            return Some((Invariant::NegativeHeld, account.held, A::ZERO));
+        }
+        if account.pending < A::ZERO {
+            return Some((Invariant::NegativePending, account.pending, A::ZERO));
+        }
+        let total = account.available + account.held + account.pending;
+        if account.total != total {
+            return Some((Invariant::Total, account.total, total));
+        }
+        // The deposits may be in the deposit store, so their sums are taken from the totals of the account.
+        let held = account.deposit_totals.disputed
+            + account
+                .authorized
+                .values()
+                .filter(|authorization| authorization.state == AuthorizationState::Authorized)
+                .map(|authorization| authorization.amount)
+                .sum::<A>();
+        if account.held != held {
+            return Some((Invariant::Held, account.held, held));
+        }
+        let pending = account.deposit_totals.pending;
+        if account.pending != pending {
+            return Some((Invariant::Pending, account.pending, pending));
+        }
+        None
+    }
+
+    // Checks the deposit totals of the account against its deposits. The account must hold every deposit.
+    pub fn check_totals<A: Amount>(account: &Account<A>) -> Option<(Invariant, Decimal, Decimal)> {
+        let kept = &account.deposit_totals;
+        let totals = DepositTotals::sum(account.deposited.values());
+        if kept.disputed != totals.disputed {
+            return Some((
+                Invariant::DisputedTotal,
+                kept.disputed.to_decimal(),
+                totals.disputed.to_decimal(),
+            ));
+        }
+        if kept.pending != totals.pending {
+            return Some((
+                Invariant::PendingTotal,
+                kept.pending.to_decimal(),
+                totals.pending.to_decimal(),
+            ));
+        }
+        if kept.charged_back != totals.charged_back {
+            return Some((
+                Invariant::ChargebackedCount,
+                Decimal::from(kept.charged_back),
+                Decimal::from(totals.charged_back),
+            ));
+        }
+        None
+    }
+}
+
+// The row or engine event after which an invariant was first broken.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Cause {
+    pub row: u64,
+    pub tx: TxId,
+    pub r#type: TransactionType,
+    pub amount: Option<Decimal>,
+    pub result: Result<(), Rejection>,
+    pub before: Balances,
+}
+
+impl Cause {
+    pub(crate) fn row(
+        row: u64,
+        transaction: &Transaction,
+        result: Result<(), Rejection>,
+        before: Balances,
+    ) -> Self {
+        Self {
+            row,
+            tx: transaction.tx,
+            r#type: transaction.r#type,
+            amount: transaction.amount,
+            result,
+            before,
+        }
+    }
+
+    pub(crate) fn event(row: u64, tx: TxId, r#type: TransactionType, before: Balances) -> Self {
+        Self {
+            row,
+            tx,
+            r#type,
+            amount: None,
+            result: Ok(()),
+            before,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Violation {
+    pub client: ClientId,
+    pub invariant: Invariant,
+    pub actual: Decimal,
+    pub expected: Decimal,
+    pub balances: Balances,
+    // None if the accounts were audited at the end.
+    pub cause: Option<Cause>,
+}
+
+impl Violation {
+    pub(crate) fn check<A: Amount>(account: &Account<A>, cause: Option<Cause>) -> Option<Self> {
+        Self::new(account, Invariant::check(account), cause)
+    }
+
+    // Also checks the deposit totals. The account must hold every deposit.
+    pub(crate) fn check_all<A: Amount>(account: &Account<A>) -> Option<Self> {
+        Self::check(account, None)
+            .or_else(|| Self::new(account, Invariant::check_totals(account), None))
+    }
+
+    fn new<A: Amount>(
+        account: &Account<A>,
+        broken: Option<(Invariant, Decimal, Decimal)>,
+        cause: Option<Cause>,
+    ) -> Option<Self> {
+        broken.map(|(invariant, actual, expected)| Self {
+            client: account.client,
+            invariant,
+            actual,
+            expected,
+            balances: Balances::from(account),
+            cause,
+        })
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "client {}: {} (is {}, expected {})",
+            self.client, self.invariant, self.actual, self.expected
+        )?;
+        if let Some(cause) = &self.cause {
+            write!(
+                f,
+                " after row {} ({} tx {}",
+                cause.row, cause.r#type, cause.tx
+            )?;
+            if let Some(amount) = cause.amount {
+                write!(f, " amount {}", amount)?;
+            }
+            match &cause.result {
+                Ok(()) => write!(f, ", applied)")?,
+                Err(rejection) => write!(f, ", rejected: {})", rejection)?,
+            }
+            write!(f, ". Balances before: {}", cause.before)?;
+        }
+        write!(f, ". Balances after: {}", self.balances)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Engine, EngineConfig, EngineError, Settlement};
+    use pretty_assertions::assert_eq;
+    use rust_decimal_macros::dec;
+    use std::fs::File;
+
+    #[test]
+    fn test_audit() -> Result<(), EngineError> {
+        for (test_file_path, settlement) in [
+            ("test_process_records.csv", Settlement::Immediate),
+            ("test_partial_disputes.csv", Settlement::Immediate),
+            ("test_dispute_lifecycle.csv", Settlement::Immediate),
+            ("test_authorizations.csv", Settlement::Immediate),
+            ("test_settlement.csv", Settlement::Delayed(2)),
+            ("test_refunds.csv", Settlement::Immediate),
+        ] {
+            let test_rdr = File::open(test_file_path)?;
+            let mut engine = Engine::new(EngineConfig {
+                settlement,
+                auth_expiry: Some(3),
+                audit: Some(AuditMode::EveryTransaction),
+                ..EngineConfig::default()
+            });
+            engine.process_records(test_rdr)?;
+            engine.audit()?;
+        }
+
+        // Corrupt the held amount of client 1 and check that the next row touching it is reported.
+        let mut engine = Engine::new(EngineConfig {
+            audit: Some(AuditMode::EveryTransaction),
+            ..EngineConfig::default()
+        });
+        let _ = engine.apply(&Transaction::new(
+            TransactionType::Deposit,
+            1,
+            1,
+            Some(dec!(10)),
+        ));
+        engine.accounts.get_mut(&1).unwrap().held = dec!(1);
+        let _ = engine.apply(&Transaction::new(
+            TransactionType::Deposit,
+            2,
+            2,
+            Some(dec!(10)),
+        ));
+        let _ = engine.apply(&Transaction::new(
+            TransactionType::Withdrawal,
+            1,
+            3,
+            Some(dec!(4)),
+        ));
+        let violation = engine.violation().unwrap();
+        assert_eq!(violation.invariant, Invariant::Total);
+        assert_eq!((violation.actual, violation.expected), (dec!(6), dec!(7)));
+        assert_eq!(violation.client, 1);
+        assert_eq!(violation.cause.as_ref().unwrap().row, 2);
+        assert_eq!(violation.cause.as_ref().unwrap().tx, 3);
+        assert!(matches!(
+            engine.audit(),
+            Err(EngineError::AuditViolation(_))
+        ));
+
+        // At the end the deposit totals are also checked against the deposits.
+        let mut engine = Engine::new(EngineConfig {
+            audit: Some(AuditMode::End),
+            ..EngineConfig::default()
+        });
+        engine.process_records(File::open("test_dispute_lifecycle.csv")?)?;
+        engine.audit()?;
+        engine
+            .accounts
+            .get_mut(&2)
+            .unwrap()
+            .deposit_totals
+            .charged_back += 1;
+        match engine.audit() {
+            Err(EngineError::AuditViolation(violation)) => {
+                assert_eq!(violation.invariant, Invariant::ChargebackedCount);
+                assert_eq!((violation.actual, violation.expected), (dec!(2), dec!(1)));
+            }
+            result => panic!("unexpected audit result {:?}", result),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,282 @@
+use crate::accounts::Accounts;
+use crate::amount::Amount;
+use crate::audit::Violation;
+use crate::duplicates::Fingerprint;
+use crate::{Account, Authorization, ClientId, Deposit, Rejection, TxId};
+use std::collections::{HashMap, VecDeque};
+use thiserror::Error;
+
+// Entry of the queue of pending expiries or settlements: when it is due, the client and the transaction.
+pub(crate) type QueueEntry = (u64, ClientId, TxId);
+
+// Undoes one change of a queue of pending expiries or settlements.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum QueueUndo {
+    // An entry was pushed at the back.
+    PopBack,
+    // The entry was popped from the front.
+    PushFront(QueueEntry),
+}
+
+// What a batch has changed, to undo it. Each account, deposit and authorization is saved when the batch first touches it:
+// an account without its deposits and authorizations, and only the deposit and authorization of the transaction that touches it.
+#[derive(Clone, Debug)]
+pub(crate) struct Checkpoint<A> {
+    // Balances and lock of the accounts. None if the account did not exist.
+    pub(crate) accounts: HashMap<ClientId, Option<Account<A>>>,
+    // None if the account had no such deposit or authorization in memory.
+    pub(crate) deposits: HashMap<(ClientId, TxId), Option<Deposit<A>>>,
+    pub(crate) authorizations: HashMap<(ClientId, TxId), Option<Authorization<A>>>,
+    // Transaction IDs first seen in the batch.
+    pub(crate) tx_ids: Vec<TxId>,
+    // Fingerprints recorded in the batch with the ones they replaced, in order.
+    pub(crate) fingerprints: Vec<(TxId, Option<Fingerprint>)>,
+    // Changes of the queues, in order.
+    pub(crate) expiries: Vec<QueueUndo>,
+    pub(crate) settlements: Vec<QueueUndo>,
+    pub(crate) ledger_entries: usize,
+    pub(crate) history_records: usize,
+    pub(crate) violation: Option<Violation>,
+    pub(crate) unsaved_tx_ids: usize,
+}
+
+impl<A: Amount> Checkpoint<A> {
+    pub(crate) fn new(
+        ledger_entries: usize,
+        history_records: usize,
+        violation: Option<Violation>,
+        unsaved_tx_ids: usize,
+    ) -> Self {
+        Self {
+            accounts: HashMap::new(),
+            deposits: HashMap::new(),
+            authorizations: HashMap::new(),
+            tx_ids: Vec::new(),
+            fingerprints: Vec::new(),
+            expiries: Vec::new(),
+            settlements: Vec::new(),
+            ledger_entries,
+            history_records,
+            violation,
+            unsaved_tx_ids,
+        }
+    }
+
+    // Saves what transaction `tx` can change in the account of the client, unless the batch has already saved it.
+    pub(crate) fn save(&mut self, accounts: &Accounts<A>, client: ClientId, tx: TxId) {
+        let account = accounts.get(&client);
+        self.accounts.entry(client).or_insert_with(|| {
+            account.map(|account| Account {
+                deposited: HashMap::new(),
+                authorized: HashMap::new(),
+                ..*account
+            })
+        });
+        self.deposits
+            .entry((client, tx))
+            .or_insert_with(|| account.and_then(|account| account.deposited.get(&tx).cloned()));
+        self.authorizations
+            .entry((client, tx))
+            .or_insert_with(|| account.and_then(|account| account.authorized.get(&tx).cloned()));
+    }
+
+    // Hands the changes of a committed inner batch to this one. What this batch saved first is kept.
+    pub(crate) fn merge(&mut self, inner: Self) {
+        for (client, account) in inner.accounts {
+            self.accounts.entry(client).or_insert(account);
+        }
+        for (key, deposit) in inner.deposits {
+            self.deposits.entry(key).or_insert(deposit);
+        }
+        for (key, authorization) in inner.authorizations {
+            self.authorizations.entry(key).or_insert(authorization);
+        }
+        self.tx_ids.extend(inner.tx_ids);
+        self.fingerprints.extend(inner.fingerprints);
+        self.expiries.extend(inner.expiries);
+        self.settlements.extend(inner.settlements);
+    }
+
+    // Puts the saved accounts, deposits and authorizations back.
+    pub(crate) fn restore_accounts(&mut self, table: &mut Accounts<A>) {
+        for (client, saved) in self.accounts.drain() {
+            match (saved, table.get_mut(&client)) {
+                (Some(saved), Some(account)) => {
+                    account.available = saved.available;
+                    account.held = saved.held;
+                    account.pending = saved.pending;
+                    account.total = saved.total;
+                    account.locked = saved.locked;
+                    account.deposit_totals = saved.deposit_totals;
+                }
+                (Some(saved), None) => {
+                    table.insert(client, saved);
+                }
+                (None, _) => {
+                    table.remove(&client);
+                }
+            }
+        }
+        for ((client, tx), deposit) in self.deposits.drain() {
+            if let Some(account) = table.get_mut(&client) {
+                match deposit {
+                    Some(deposit) => account.deposited.insert(tx, deposit),
+                    None => account.deposited.remove(&tx),
+                };
+            }
+        }
+        for ((client, tx), authorization) in self.authorizations.drain() {
+            if let Some(account) = table.get_mut(&client) {
+                match authorization {
+                    Some(authorization) => account.authorized.insert(tx, authorization),
+                    None => account.authorized.remove(&tx),
+                };
+            }
+        }
+    }
+}
+
+// Undoes the changes of a queue, last first.
+pub(crate) fn undo_queue(queue: &mut VecDeque<QueueEntry>, changes: Vec<QueueUndo>) {
+    for change in changes.into_iter().rev() {
+        match change {
+            QueueUndo::PopBack => {
+                queue.pop_back();
+            }
+            QueueUndo::PushFront(entry) => queue.push_front(entry),
+        }
+    }
+}
+
+// A batch is rolled back because one of its transactions is rejected.
+#[derive(Error, Clone, PartialEq, Eq, Debug)]
+#[error("Transaction {index} of the batch is rejected: {rejection}")]
+pub struct BatchRejection {
+    // Position of the rejected transaction in the batch.
+    pub index: usize,
+    pub rejection: Rejection,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::SqliteDeposits;
+    use crate::{Engine, EngineConfig, EngineError, Settlement, Transaction, TransactionType};
+    use pretty_assertions::assert_eq;
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    fn row(
+        r#type: TransactionType,
+        client: ClientId,
+        tx: TxId,
+        amount: Option<Decimal>,
+    ) -> Transaction {
+        Transaction::new(r#type, client, tx, amount)
+    }
+
+    #[test]
+    fn test_batch_rollback() -> Result<(), EngineError> {
+        let config = EngineConfig {
+            auth_expiry: Some(1),
+            settlement: Settlement::Delayed(1),
+            ledger: true,
+            history: true,
+            ..EngineConfig::default()
+        };
+        let engines = [
+            Engine::<Decimal>::new(config.clone()),
+            Engine::with_deposit_store(config.clone(), Box::new(SqliteDeposits::temporary()?)),
+        ];
+        for mut engine in engines {
+            // Deposit 1 settles before the authorization, deposit 2 and the authorization are still queued when the batch begins.
+            for transaction in [
+                row(TransactionType::Deposit, 1, 1, Some(dec!(100))),
+                row(TransactionType::Deposit, 2, 2, Some(dec!(50))),
+                row(TransactionType::Authorize, 1, 3, Some(dec!(10))),
+            ] {
+                assert_eq!(engine.apply(&transaction), Ok(()));
+            }
+            let accounts = engine.accounts.clone();
+            let expiries = engine.expiries.clone();
+            let settlements = engine.settlements.clone();
+            let ledger = engine.ledger.as_ref().map(|l| l.entries().to_vec());
+            let history_records = engine.history.as_ref().map(|h| h.records().len());
+
+            // Deposit 2 settles and the authorization expires inside the batch, which then fails on its last row.
+            let rejection = engine.apply_batch(&[
+                row(TransactionType::Deposit, 3, 4, Some(dec!(20))),
+                row(TransactionType::Dispute, 1, 1, None),
+                row(TransactionType::Withdrawal, 2, 5, Some(dec!(5))),
+                row(TransactionType::Deposit, 1, 1, Some(dec!(5))),
+            ]);
+            assert_eq!(
+                rejection,
+                Err(BatchRejection {
+                    index: 3,
+                    rejection: Rejection::DuplicateTx
+                })
+            );
+            assert_eq!(engine.accounts, accounts);
+            assert_eq!(engine.expiries, expiries);
+            assert_eq!(engine.settlements, settlements);
+            assert_eq!(engine.ledger.as_ref().map(|l| l.entries().to_vec()), ledger);
+            assert_eq!(
+                engine.history.as_ref().map(|h| h.records().len()),
+                history_records
+            );
+            for tx in [4, 5] {
+                assert!(!engine.tx_set.contains(tx));
+            }
+            assert!(engine.tx_set.contains(1));
+
+            // The queues are intact: the next row settles deposit 2 and expires the authorization.
+            assert_eq!(
+                engine.apply(&row(TransactionType::Deposit, 3, 4, Some(dec!(20)))),
+                Ok(())
+            );
+            assert_eq!(engine.accounts()[&2].available(), dec!(50));
+            assert_eq!(engine.accounts()[&1].available(), dec!(100));
+            assert_eq!(engine.accounts()[&1].held(), Decimal::ZERO);
+            engine.verify_ledger()?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_nested_batch_rollback() -> Result<(), EngineError> {
+        let mut engine = Engine::<Decimal>::new(EngineConfig {
+            ledger: true,
+            ..EngineConfig::default()
+        });
+        assert_eq!(
+            engine.apply(&row(TransactionType::Deposit, 1, 1, Some(dec!(10)))),
+            Ok(())
+        );
+        let accounts = engine.accounts.clone();
+        engine.begin_batch();
+        assert_eq!(
+            engine.apply(&row(TransactionType::Withdrawal, 1, 2, Some(dec!(3)))),
+            Ok(())
+        );
+        // The inner batch disputes the deposit that the outer batch has already saved the account of.
+        engine.begin_batch();
+        assert_eq!(
+            engine.apply(&row(TransactionType::Dispute, 1, 1, None)),
+            Ok(())
+        );
+        assert_eq!(
+            engine.apply(&row(TransactionType::Deposit, 2, 3, Some(dec!(4)))),
+            Ok(())
+        );
+        engine.commit_batch();
+        assert_eq!(engine.accounts()[&1].held(), dec!(10));
+        engine.rollback_batch();
+        assert_eq!(engine.accounts, accounts);
+        for tx in [2, 3] {
+            assert!(!engine.tx_set.contains(tx));
+        }
+        engine.verify_ledger()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,247 @@
+use crate::{ClientId, Rejection, Transaction, TransactionType, TxId};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+
+// How a deposit, withdrawal or authorization reusing a transaction ID is handled.
+// By default every reuse is rejected as `Rejection::DuplicateTx`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct DuplicatePolicy {
+    // Remember a fingerprint of every row, so that an exact replay of a row is acknowledged as `Rejection::Replayed`
+    // and a different row reusing its ID is reported as `Rejection::ConflictingTx`.
+    pub fingerprints: bool,
+    // Let a row reuse the ID of an earlier row that was rejected because its amount is not a valid Decimal number.
+    pub replace_invalid: bool,
+}
+
+impl DuplicatePolicy {
+    pub fn is_enabled(&self) -> bool {
+        self.fingerprints || self.replace_invalid
+    }
+}
+
+// What is remembered of the row that claimed a transaction ID.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Fingerprint {
+    r#type: TransactionType,
+    client: ClientId,
+    // Normalized, so that 1.5 and 1.50 are the same amount.
+    amount: Option<Decimal>,
+    // The row was rejected because its amount is not a valid Decimal number.
+    invalid_amount: bool,
+}
+
+impl Fingerprint {
+    pub fn new(transaction: &Transaction, result: &Result<(), Rejection>) -> Self {
+        Self {
+            r#type: transaction.r#type(),
+            client: transaction.client(),
+            amount: transaction.amount().map(|amount| amount.normalize()),
+            invalid_amount: *result == Err(Rejection::InvalidAmount),
+        }
+    }
+
+    // Whether `transaction` is an exact replay of the row.
+    fn matches(&self, transaction: &Transaction) -> bool {
+        self.r#type == transaction.r#type()
+            && self.client == transaction.client()
+            && self.amount == transaction.amount().map(|amount| amount.normalize())
+    }
+}
+
+// Fingerprints of the rows that claimed a transaction ID, by ID. They are not saved to the database,
+// so an ID used by an earlier run is rejected as `Rejection::DuplicateTx` whatever the policy.
+// The command line does not allow a duplicate policy together with `--db` for that reason.
+#[derive(Clone, Debug, Default)]
+pub struct Fingerprints {
+    rows: HashMap<TxId, Fingerprint>,
+}
+
+impl Fingerprints {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Remembers the row that claimed the ID. Returns the fingerprint it replaces.
+    pub fn insert(&mut self, tx: TxId, fingerprint: Fingerprint) -> Option<Fingerprint> {
+        self.rows.insert(tx, fingerprint)
+    }
+
+    // Puts back the fingerprint a rolled back row replaced, or forgets the ID if the row was the first to use it.
+    pub fn restore(&mut self, tx: TxId, previous: Option<Fingerprint>) {
+        match previous {
+            Some(fingerprint) => self.rows.insert(tx, fingerprint),
+            None => self.rows.remove(&tx),
+        };
+    }
+
+    // Decides how a row reusing the ID of an earlier row is handled. Ok if the row is processed as if the ID were new.
+    pub fn reuse(
+        &self,
+        transaction: &Transaction,
+        policy: &DuplicatePolicy,
+    ) -> Result<(), Rejection> {
+        // The ID was used by an earlier run.
+        let earlier = self
+            .rows
+            .get(&transaction.tx())
+            .ok_or(Rejection::DuplicateTx)?;
+        if policy.fingerprints && earlier.matches(transaction) {
+            return Err(Rejection::Replayed);
+        }
+        if policy.replace_invalid && earlier.invalid_amount {
+            return Ok(());
+        }
+        if policy.fingerprints {
+            return Err(Rejection::ConflictingTx);
+        }
+        Err(Rejection::DuplicateTx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::HistoryFilter;
+    use crate::{Engine, EngineConfig, EngineError};
+    use pretty_assertions::assert_eq;
+    use rust_decimal_macros::dec;
+
+    fn run(input: &str, duplicates: DuplicatePolicy) -> Result<Engine, EngineError> {
+        let mut engine = Engine::new(EngineConfig {
+            duplicates,
+            history: true,
+            ..EngineConfig::default()
+        });
+        engine.process_records(input.as_bytes())?;
+        Ok(engine)
+    }
+
+    #[test]
+    fn test_duplicate_policy() -> Result<(), EngineError> {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,10\n\
+                     deposit,1,1,10.00\n\
+                     deposit,1,1,5\n\
+                     withdrawal,2,1,10\n\
+                     deposit,1,2,\n\
+                     deposit,1,2,3\n\
+                     deposit,1,2,3\n\
+                     dispute,1,1,\n";
+        let policy = |fingerprints, replace_invalid| DuplicatePolicy {
+            fingerprints,
+            replace_invalid,
+        };
+        use Rejection::*;
+        let cases = [
+            (
+                policy(false, false),
+                [
+                    Ok(()),
+                    Err(DuplicateTx),
+                    Err(DuplicateTx),
+                    Err(DuplicateTx),
+                    Err(InvalidAmount),
+                    Err(DuplicateTx),
+                    Err(DuplicateTx),
+                    Ok(()),
+                ],
+            ),
+            (
+                policy(true, false),
+                [
+                    Ok(()),
+                    Err(Replayed),
+                    Err(ConflictingTx),
+                    Err(ConflictingTx),
+                    Err(InvalidAmount),
+                    Err(ConflictingTx),
+                    Err(ConflictingTx),
+                    Ok(()),
+                ],
+            ),
+            (
+                policy(false, true),
+                [
+                    Ok(()),
+                    Err(DuplicateTx),
+                    Err(DuplicateTx),
+                    Err(DuplicateTx),
+                    Err(InvalidAmount),
+                    Ok(()),
+                    Err(DuplicateTx),
+                    Ok(()),
+                ],
+            ),
+            (
+                policy(true, true),
+                [
+                    Ok(()),
+                    Err(Replayed),
+                    Err(ConflictingTx),
+                    Err(ConflictingTx),
+                    Err(InvalidAmount),
+                    Ok(()),
+                    Err(Replayed),
+                    Ok(()),
+                ],
+            ),
+        ];
+        for (duplicates, expected) in cases {
+            let engine = run(input, duplicates)?;
+            let results: Vec<_> = engine
+                .query(&HistoryFilter::default())
+                .iter()
+                .map(|record| record.result.clone())
+                .collect();
+            assert_eq!(results, expected, "{:?}", duplicates);
+            let deposit = if duplicates.replace_invalid {
+                dec!(3)
+            } else {
+                dec!(0)
+            };
+            assert_eq!(engine.accounts()[&1].available(), deposit);
+            assert_eq!(engine.accounts()[&1].held(), dec!(10));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_duplicates_in_batches() -> Result<(), EngineError> {
+        // The replay does not roll back batch b1. Batch b2 is rolled back and leaves no history,
+        // so tx 2 can replace the invalid row again.
+        let input = "type,client,tx,amount,batch\n\
+                     deposit,1,1,10,\n\
+                     deposit,1,2,,\n\
+                     deposit,1,1,10,b1\n\
+                     deposit,1,3,1,b1\n\
+                     deposit,1,2,4,b2\n\
+                     withdrawal,1,4,100,b2\n\
+                     deposit,1,2,5,\n\
+                     deposit,1,3,1,\n";
+        let engine = run(
+            input,
+            DuplicatePolicy {
+                fingerprints: true,
+                replace_invalid: true,
+            },
+        )?;
+        let results: Vec<_> = engine
+            .query(&HistoryFilter::default())
+            .iter()
+            .map(|record| (record.tx, record.result.clone()))
+            .collect();
+        assert_eq!(
+            results,
+            vec![
+                (1, Ok(())),
+                (2, Err(Rejection::InvalidAmount)),
+                (1, Err(Rejection::Replayed)),
+                (3, Ok(())),
+                (2, Ok(())),
+                (3, Err(Rejection::Replayed)),
+            ]
+        );
+        assert_eq!(engine.accounts()[&1].available(), dec!(16));
+        Ok(())
+    }
+}
@@ -0,0 +1,202 @@
+use crate::history::HistoryRecord;
+use crate::precision::Precision;
+use crate::{Balances, DepositState, EngineError, TransactionType, TxId};
+use rust_decimal::Decimal;
+use std::io;
+
+// One row or engine event referencing the explained tx.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Step<'a> {
+    pub record: &'a HistoryRecord,
+    // State of the client's deposit with this tx before the step, if there was one.
+    pub deposit_state_before: Option<DepositState>,
+}
+
+// Lifecycle of a transaction ID: every row and engine event referencing it in the order they were applied.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Explanation<'a> {
+    pub tx: TxId,
+    pub steps: Vec<Step<'a>>,
+    // Amounts are written with the scale of the engine.
+    pub precision: Precision,
+}
+
+impl<'a> Explanation<'a> {
+    pub fn new<I>(tx: TxId, records: I, precision: Precision) -> Self
+    where
+        I: IntoIterator<Item = &'a HistoryRecord>,
+    {
+        let mut steps: Vec<Step> = Vec::new();
+        for record in records {
+            // A deposit state belongs to the client that made the deposit, so only earlier steps of the same client count.
+            let deposit_state_before = steps
+                .iter()
+                .rev()
+                .find(|step| step.record.client == record.client)
+                .and_then(|step| step.record.deposit_state);
+            steps.push(Step {
+                record,
+                deposit_state_before,
+            });
+        }
+        Self {
+            tx,
+            steps,
+            precision,
+        }
+    }
+
+    pub fn write_text<W: io::Write>(&self, mut wtr: W) -> Result<(), EngineError> {
+        if self.steps.is_empty() {
+            writeln!(wtr, "Transaction {} does not appear in the input.", self.tx)?;
+            return Ok(());
+        }
+        writeln!(
+            wtr,
+            "Transaction {} appears in {} rows or engine events.",
+            self.tx,
+            self.steps.len()
+        )?;
+        let precision = &self.precision;
+        for step in &self.steps {
+            let record = step.record;
+            write!(
+                wtr,
+                "Row {}: {} by client {}",
+                record.row, record.r#type, record.client
+            )?;
+            if let Some(amount) = record.amount {
+                write!(wtr, " of {}", precision.format(amount))?;
+            }
+            writeln!(wtr)?;
+            match &record.result {
+                Ok(()) => writeln!(wtr, "  Applied: {}.", purpose(record))?,
+                Err(rejection) => writeln!(wtr, "  Ignored: {}.", rejection)?,
+            }
+            if record.before != record.balances {
+                writeln!(
+                    wtr,
+                    "  Changes: {}.",
+                    changes(
+                        &precision.format_balances(&record.before),
+                        &precision.format_balances(&record.balances)
+                    )
+                )?;
+            }
+            writeln!(
+                wtr,
+                "  Before: {}.",
+                precision.format_balances(&record.before)
+            )?;
+            writeln!(
+                wtr,
+                "  After: {}.",
+                precision.format_balances(&record.balances)
+            )?;
+            match (step.deposit_state_before, record.deposit_state) {
+                (Some(before), Some(after)) if before != after => {
+                    writeln!(wtr, "  Deposit state: {:?} -> {:?}.", before, after)?
+                }
+                (None, Some(after)) => writeln!(wtr, "  Deposit state: {:?}.", after)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+// What an applied transaction type does to the account.
+fn purpose(record: &HistoryRecord) -> &'static str {
+    match record.r#type {
+        TransactionType::Deposit if record.balances.pending > record.before.pending => {
+            "the deposit is pending until it settles"
+        }
+        TransactionType::Deposit => "the client is credited",
+        TransactionType::Withdrawal => "the client is debited",
+        TransactionType::Authorize => "the authorized funds are held",
+        TransactionType::Capture => "the captured funds are debited and the rest is released",
+        TransactionType::Void | TransactionType::Expire => "the authorized funds are released",
+        TransactionType::Settle => "the pending deposit becomes available",
+        TransactionType::Refund => "the refunded funds are returned to the payer",
+        TransactionType::Dispute => "the disputed funds are held",
+        TransactionType::Resolve => "the disputed funds are released",
+        TransactionType::Chargeback => "the disputed funds are reversed and the account is locked",
+        TransactionType::Representment => "the charged back funds are credited again",
+        TransactionType::PreArbitration => "the represented funds are held until arbitration",
+        TransactionType::ArbitrationWon => "the held funds are released",
+        TransactionType::ArbitrationLost => "the held funds are reversed and the account is locked",
+        TransactionType::Unknown => "the account is updated",
+    }
+}
+
+fn changes(before: &Balances, after: &Balances) -> String {
+    let mut changes = Vec::new();
+    for (field, before, after) in [
+        ("available", before.available, after.available),
+        ("held", before.held, after.held),
+        ("pending", before.pending, after.pending),
+        ("total", before.total, after.total),
+    ] {
+        let delta = after - before;
+        if delta > Decimal::ZERO {
+            changes.push(format!("{} +{}", field, delta));
+        } else if delta < Decimal::ZERO {
+            changes.push(format!("{} {}", field, delta));
+        }
+    }
+    changes.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Engine, EngineConfig};
+    use pretty_assertions::assert_eq;
+    use std::fs::File;
+
+    #[test]
+    fn test_explain() -> Result<(), EngineError> {
+        let test_file_path = "test_process_records.csv";
+        let test_rdr = File::open(test_file_path)?;
+        let mut engine = Engine::new(EngineConfig {
+            history: true,
+            ..EngineConfig::default()
+        });
+        engine.process_records(test_rdr)?;
+        let explanation = engine.explain(2);
+        assert_eq!(
+            explanation
+                .steps
+                .iter()
+                .map(|step| step.record.row)
+                .collect::<Vec<_>>(),
+            vec![1, 11, 14, 15, 17, 25, 45, 46]
+        );
+        let mut output = Vec::new();
+        explanation.write_text(&mut output)?;
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with(
+            "Transaction 2 appears in 8 rows or engine events.\n\
+             Row 1: deposit by client 2 of 2.0000\n  \
+             Applied: the client is credited.\n  \
+             Changes: available +2.0000, total +2.0000.\n  \
+             Before: available 0.0000, held 0.0000, pending 0.0000, total 0.0000.\n  \
+             After: available 2.0000, held 0.0000, pending 0.0000, total 2.0000.\n  \
+             Deposit state: NotDisputed.\n"
+        ));
+        // Client 1 references client 2's deposit.
+        assert!(output.contains(
+            "Row 11: dispute by client 1\n  \
+             Ignored: Either the tx specified doesn't exist or the specified tx is not a deposit or the specified tx belongs to a different client.\n"
+        ));
+        assert!(output.contains(
+            "Row 45: chargeback by client 2\n  \
+             Applied: the disputed funds are reversed and the account is locked.\n  \
+             Changes: held -2.0000, total -2.0000.\n  \
+             Before: available 0.0000, held 2.0000, pending 0.0000, total 2.0000.\n  \
+             After: available 0.0000, held 0.0000, pending 0.0000, total 0.0000.\n  \
+             Deposit state: Disputed -> Chargebacked.\n"
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,290 @@
+use crate::{Account, ClientId, EngineError, TxId};
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::io;
+
+#[derive(Clone, Debug)]
+pub struct GeneratorConfig {
+    // The same seed and config always generate the same rows.
+    pub seed: u64,
+    pub clients: ClientId,
+    pub rows: usize,
+    // Relative weights of deposits and withdrawals among the regular rows.
+    pub deposit_weight: u32,
+    pub withdrawal_weight: u32,
+    // Probability that a row disputes an earlier deposit.
+    pub dispute_rate: f64,
+    // Probability that a row closes an open dispute, and that it closes it with a chargeback rather than a resolve.
+    pub close_rate: f64,
+    pub chargeback_rate: f64,
+    // Probability that a row reuses the ID of an earlier transaction.
+    pub duplicate_rate: f64,
+    // Probability that a row has an unknown type, an invalid or negative amount, or references an unknown deposit.
+    pub malformed_rate: f64,
+    // Probability that a field is padded with whitespaces.
+    pub whitespace_rate: f64,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            clients: 100,
+            rows: 10_000,
+            deposit_weight: 2,
+            withdrawal_weight: 1,
+            dispute_rate: 0.05,
+            close_rate: 0.05,
+            chargeback_rate: 0.3,
+            duplicate_rate: 0.01,
+            malformed_rate: 0.01,
+            whitespace_rate: 0.1,
+        }
+    }
+}
+
+// Generates a transaction stream together with the accounts it is expected to produce.
+// The expected accounts are computed by a model of the engine rules that does not share any code with `Account::update`, so that the engine can be tested against it.
+pub struct Generator {
+    config: GeneratorConfig,
+    rng: ChaCha8Rng,
+    accounts: HashMap<ClientId, Account>,
+    // The last transaction ID used. IDs are handed out in increasing order.
+    last_tx: TxId,
+    // Deposits that can be disputed and deposits under dispute: (client, tx, amount).
+    undisputed: Vec<(ClientId, TxId, Decimal)>,
+    disputed: Vec<(ClientId, TxId, Decimal)>,
+}
+
+impl Generator {
+    pub fn new(config: GeneratorConfig) -> Self {
+        Self {
+            rng: ChaCha8Rng::seed_from_u64(config.seed),
+            config,
+            accounts: HashMap::new(),
+            last_tx: 0,
+            undisputed: Vec::new(),
+            disputed: Vec::new(),
+        }
+    }
+
+    // Accounts the rows written so far are expected to produce.
+    pub fn accounts(&self) -> &HashMap<ClientId, Account> {
+        &self.accounts
+    }
+
+    pub fn into_accounts(self) -> HashMap<ClientId, Account> {
+        self.accounts
+    }
+
+    pub fn write_csv<W: io::Write>(&mut self, wtr: W) -> Result<(), EngineError> {
+        let mut writer = csv::Writer::from_writer(wtr);
+        writer.write_record(["type", "client", "tx", "amount"])?;
+        for _ in 0..self.config.rows {
+            let [r#type, client, tx, amount] = self.next_row();
+            let record = [
+                self.noise(r#type),
+                self.noise(client.to_string()),
+                self.noise(tx.to_string()),
+                self.noise(amount),
+            ];
+            writer.write_record(&record)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn write_accounts<W: io::Write>(&self, wtr: W) -> Result<(), EngineError> {
+        let mut writer = csv::Writer::from_writer(wtr);
+        let mut clients: Vec<&ClientId> = self.accounts.keys().collect();
+        clients.sort_unstable();
+        for client in clients {
+            writer.serialize(&self.accounts[client])?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn noise(&mut self, field: String) -> String {
+        if self.rng.gen_bool(self.config.whitespace_rate) {
+            let before = self.rng.gen_range(0..3);
+            let after = self.rng.gen_range(0..3);
+            format!("{}{}{}", " ".repeat(before), field, " ".repeat(after))
+        } else {
+            field
+        }
+    }
+
+    fn next_tx(&mut self) -> TxId {
+        self.last_tx += 1;
+        self.last_tx
+    }
+
+    fn amount(&mut self) -> Decimal {
+        Decimal::new(self.rng.gen_range(1..=10_000_000), 4)
+    }
+
+    // Every row creates the account of its client, even if it is rejected.
+    fn account(&mut self, client: ClientId) -> &mut Account {
+        self.accounts
+            .entry(client)
+            .or_insert_with(|| Account::new(client))
+    }
+
+    // Returns the fields of the next row and applies it to the expected accounts.
+    fn next_row(&mut self) -> [String; 4] {
+        let config = self.config.clone();
+        let client = self.rng.gen_range(1..=config.clients.max(1));
+        let roll: f64 = self.rng.gen();
+        let mut threshold = config.duplicate_rate;
+        if roll < threshold && self.last_tx > 0 {
+            return self.duplicate(client);
+        }
+        threshold += config.malformed_rate;
+        if roll < threshold {
+            return self.malformed(client);
+        }
+        threshold += config.dispute_rate;
+        if roll < threshold && !self.undisputed.is_empty() {
+            return self.dispute();
+        }
+        threshold += config.close_rate;
+        if roll < threshold && !self.disputed.is_empty() {
+            return self.close_dispute();
+        }
+        let weight = config.deposit_weight + config.withdrawal_weight;
+        if self.rng.gen_range(0..weight.max(1)) < config.deposit_weight {
+            self.deposit(client)
+        } else {
+            self.withdrawal(client)
+        }
+    }
+
+    fn deposit(&mut self, client: ClientId) -> [String; 4] {
+        let tx = self.next_tx();
+        let amount = self.amount();
+        let account = self.account(client);
+        if !account.locked {
+            account.available += amount;
+            account.total += amount;
+            self.undisputed.push((client, tx, amount));
+        }
+        row("deposit", client, tx, amount.to_string())
+    }
+
+    fn withdrawal(&mut self, client: ClientId) -> [String; 4] {
+        let tx = self.next_tx();
+        let amount = self.amount();
+        let account = self.account(client);
+        if !account.locked && account.available >= amount {
+            account.available -= amount;
+            account.total -= amount;
+        }
+        row("withdrawal", client, tx, amount.to_string())
+    }
+
+    // A deposit or withdrawal reusing an earlier ID is ignored.
+    fn duplicate(&mut self, client: ClientId) -> [String; 4] {
+        let tx = self.rng.gen_range(1..=self.last_tx);
+        let amount = self.amount();
+        self.account(client);
+        let r#type = *["deposit", "withdrawal"].choose(&mut self.rng).unwrap();
+        row(r#type, client, tx, amount.to_string())
+    }
+
+    fn malformed(&mut self, client: ClientId) -> [String; 4] {
+        self.account(client);
+        match self.rng.gen_range(0..4) {
+            // Rows of an unknown type do not use up their ID.
+            0 => row(
+                "transfer",
+                client,
+                self.last_tx + 1,
+                self.amount().to_string(),
+            ),
+            // Deposits with an invalid amount and withdrawals with a negative amount are ignored but use up their ID.
+            1 => row("deposit", client, self.next_tx(), "1.2.3".to_string()),
+            2 => {
+                let amount = self.amount();
+                row("withdrawal", client, self.next_tx(), format!("-{}", amount))
+            }
+            // The next ID has not been used by any deposit yet.
+            _ => row("dispute", client, self.last_tx + 1, String::new()),
+        }
+    }
+
+    fn dispute(&mut self) -> [String; 4] {
+        let index = self.rng.gen_range(0..self.undisputed.len());
+        let (client, tx, amount) = self.undisputed.swap_remove(index);
+        let account = self.account(client);
+        account.available -= amount;
+        account.held += amount;
+        self.disputed.push((client, tx, amount));
+        row("dispute", client, tx, String::new())
+    }
+
+    fn close_dispute(&mut self) -> [String; 4] {
+        let index = self.rng.gen_range(0..self.disputed.len());
+        let (client, tx, amount) = self.disputed.swap_remove(index);
+        let chargeback = self.rng.gen_bool(self.config.chargeback_rate);
+        let account = self.account(client);
+        account.held -= amount;
+        if chargeback {
+            account.total -= amount;
+            account.locked = true;
+            row("chargeback", client, tx, String::new())
+        } else {
+            account.available += amount;
+            self.undisputed.push((client, tx, amount));
+            row("resolve", client, tx, String::new())
+        }
+    }
+}
+
+fn row(r#type: &str, client: ClientId, tx: TxId, amount: String) -> [String; 4] {
+    [
+        r#type.to_string(),
+        client.to_string(),
+        tx.to_string(),
+        amount,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process_records;
+    use crate::reconcile::{Reconciliation, Tolerances};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_generate() -> Result<(), EngineError> {
+        let config = GeneratorConfig {
+            seed: 7,
+            clients: 20,
+            rows: 5_000,
+            dispute_rate: 0.1,
+            close_rate: 0.1,
+            duplicate_rate: 0.05,
+            malformed_rate: 0.05,
+            ..GeneratorConfig::default()
+        };
+        let mut generator = Generator::new(config.clone());
+        let mut transactions = Vec::new();
+        generator.write_csv(&mut transactions)?;
+        let actual = process_records(transactions.as_slice())?;
+        let reconciliation =
+            Reconciliation::new(generator.accounts(), &actual, &Tolerances::default());
+        assert_eq!(reconciliation.differences, vec![]);
+        assert!(actual.values().any(|account| account.locked));
+
+        // The same seed generates the same rows.
+        let mut again = Vec::new();
+        Generator::new(config).write_csv(&mut again)?;
+        assert_eq!(transactions, again);
+        Ok(())
+    }
+}
@@ -0,0 +1,250 @@
+use crate::accounts::Accounts;
+use crate::amount::Amount;
+use crate::{
+    Account, Balances, ClientId, DepositState, Rejection, Transaction, TransactionType, TxId,
+};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::ops::Range;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct HistoryRecord {
+    // Position of the row in the input. Expiries and settlements triggered by the engine have the position of the row they were applied before.
+    pub row: u64,
+    pub client: ClientId,
+    pub tx: TxId,
+    pub r#type: TransactionType,
+    pub amount: Option<Decimal>,
+    pub result: Result<(), Rejection>,
+    // Client balances before and after the transaction.
+    pub before: Balances,
+    pub balances: Balances,
+    // State of the referenced deposit after the transaction, if tx is a deposit of the client.
+    pub deposit_state: Option<DepositState>,
+}
+
+impl HistoryRecord {
+    pub fn row<A: Amount>(
+        row: u64,
+        transaction: &Transaction,
+        result: Result<(), Rejection>,
+        before: Balances,
+        account: &Account<A>,
+    ) -> Self {
+        Self {
+            row,
+            client: transaction.client,
+            tx: transaction.tx,
+            r#type: transaction.r#type,
+            amount: transaction.amount,
+            result,
+            before,
+            balances: Balances::from(account),
+            deposit_state: account.deposited(transaction.tx).map(|d| d.state),
+        }
+    }
+
+    // An expiry or settlement triggered by the engine rather than by a row.
+    pub fn event<A: Amount>(
+        row: u64,
+        tx: TxId,
+        r#type: TransactionType,
+        before: Balances,
+        account: &Account<A>,
+    ) -> Self {
+        Self {
+            row,
+            client: account.client,
+            tx,
+            r#type,
+            amount: None,
+            result: Ok(()),
+            before,
+            balances: Balances::from(account),
+            deposit_state: account.deposited(tx).map(|d| d.state),
+        }
+    }
+
+    pub fn applied(&self) -> bool {
+        self.result.is_ok()
+    }
+}
+
+// Every condition that is set has to match.
+#[derive(Clone, Default, Debug)]
+pub struct HistoryFilter {
+    pub client: Option<ClientId>,
+    pub r#type: Option<TransactionType>,
+    pub applied: Option<bool>,
+    // State the referenced deposit is in after processing.
+    pub deposit_state: Option<DepositState>,
+    pub rows: Option<Range<u64>>,
+}
+
+impl HistoryFilter {
+    fn matches<A: Amount>(&self, record: &HistoryRecord, accounts: &Accounts<A>) -> bool {
+        if let Some(client) = self.client {
+            if record.client != client {
+                return false;
+            }
+        }
+        if let Some(r#type) = self.r#type {
+            if record.r#type != r#type {
+                return false;
+            }
+        }
+        if let Some(applied) = self.applied {
+            if record.applied() != applied {
+                return false;
+            }
+        }
+        if let Some(state) = self.deposit_state {
+            let deposit = accounts
+                .get(&record.client)
+                .and_then(|account| account.deposited(record.tx));
+            if deposit.map(|d| d.state) != Some(state) {
+                return false;
+            }
+        }
+        if let Some(rows) = &self.rows {
+            if !rows.contains(&record.row) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+// Every row the engine has processed together with its outcome, indexed by client and tx.
+#[derive(Clone, Default, Debug)]
+pub struct History {
+    records: Vec<HistoryRecord>,
+    by_client: HashMap<ClientId, Vec<usize>>,
+    by_tx: HashMap<TxId, Vec<usize>>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, record: HistoryRecord) {
+        let idx = self.records.len();
+        self.by_client.entry(record.client).or_default().push(idx);
+        self.by_tx.entry(record.tx).or_default().push(idx);
+        self.records.push(record);
+    }
+
+    // Removes the records after the first `len` records.
+    pub fn truncate(&mut self, len: usize) {
+        while self.records.len() > len {
+            let record = self.records.pop().unwrap();
+            // Indices are pushed in order, so the removed record is the last one of its client and tx.
+            if let Some(indices) = self.by_client.get_mut(&record.client) {
+                indices.pop();
+            }
+            if let Some(indices) = self.by_tx.get_mut(&record.tx) {
+                indices.pop();
+            }
+        }
+    }
+
+    pub fn records(&self) -> &[HistoryRecord] {
+        &self.records
+    }
+
+    pub fn client(&self, client: ClientId) -> impl Iterator<Item = &HistoryRecord> {
+        self.by_client
+            .get(&client)
+            .into_iter()
+            .flatten()
+            .map(move |&idx| &self.records[idx])
+    }
+
+    pub fn transaction(&self, tx: TxId) -> impl Iterator<Item = &HistoryRecord> {
+        self.by_tx
+            .get(&tx)
+            .into_iter()
+            .flatten()
+            .map(move |&idx| &self.records[idx])
+    }
+
+    pub fn query<'a, A: Amount>(
+        &'a self,
+        filter: &HistoryFilter,
+        accounts: &Accounts<A>,
+    ) -> Vec<&'a HistoryRecord> {
+        let candidates: Box<dyn Iterator<Item = &HistoryRecord>> = match filter.client {
+            Some(client) => Box::new(self.client(client)),
+            None => Box::new(self.records.iter()),
+        };
+        candidates
+            .filter(|record| filter.matches(record, accounts))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Engine, EngineConfig, EngineError};
+    use pretty_assertions::assert_eq;
+    use std::fs::File;
+
+    #[test]
+    fn test_history() -> Result<(), EngineError> {
+        let test_file_path = "test_process_records.csv";
+        let test_rdr = File::open(test_file_path)?;
+        let mut engine = Engine::new(EngineConfig {
+            history: true,
+            ..EngineConfig::default()
+        });
+        engine.process_records(test_rdr)?;
+
+        let withdrawals: Vec<_> = engine
+            .transaction(4)
+            .iter()
+            .map(|r| (r.row, r.r#type.as_str(), r.result.clone()))
+            .collect();
+        assert_eq!(
+            withdrawals,
+            vec![
+                (3, "withdrawal", Ok(())),
+                (12, "withdrawal", Err(Rejection::DuplicateTx)),
+                (23, "withdrawal", Err(Rejection::DuplicateTx)),
+                (27, "dispute", Err(Rejection::UnknownDeposit)),
+                (55, "resolve", Err(Rejection::UnknownDeposit)),
+            ]
+        );
+
+        let client3 = engine.history(3);
+        assert_eq!(client3.len(), 10);
+        assert!(client3.iter().all(|r| r.client == 3));
+        assert_eq!(
+            client3.last().unwrap().balances.held,
+            engine.accounts()[&3].held()
+        );
+
+        let disputes = engine.query(&HistoryFilter {
+            client: Some(1),
+            r#type: Some(TransactionType::Dispute),
+            applied: Some(true),
+            ..HistoryFilter::default()
+        });
+        assert_eq!(
+            disputes.iter().map(|r| r.row).collect::<Vec<_>>(),
+            vec![7, 10, 21]
+        );
+
+        let still_disputed = engine.query(&HistoryFilter {
+            deposit_state: Some(DepositState::Disputed),
+            rows: Some(0..40),
+            ..HistoryFilter::default()
+        });
+        assert_eq!(
+            still_disputed.iter().map(|r| r.row).collect::<Vec<_>>(),
+            vec![33]
+        );
+        Ok(())
+    }
+}
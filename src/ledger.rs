@@ -0,0 +1,307 @@
+use crate::accounts::Accounts;
+use crate::amount::Amount;
+use crate::precision::Precision;
+use crate::{ClientId, EngineError, TransactionType, TxId};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+
+// Accounts of the double-entry ledger. Each client has an available, a held and a pending account.
+// The house accounts hold the other side of every movement of funds in or out of client accounts.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub enum LedgerAccount {
+    Available(ClientId),
+    Held(ClientId),
+    Pending(ClientId),
+    // Funds received from or paid out to the bank: deposits, withdrawals, captures and refunds.
+    Settlement,
+    // Funds reversed or returned by the card networks: chargebacks, representments and arbitration.
+    CardNetwork,
+}
+
+impl fmt::Display for LedgerAccount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LedgerAccount::Available(client) => write!(f, "client:{}:available", client),
+            LedgerAccount::Held(client) => write!(f, "client:{}:held", client),
+            LedgerAccount::Pending(client) => write!(f, "client:{}:pending", client),
+            LedgerAccount::Settlement => write!(f, "house:settlement"),
+            LedgerAccount::CardNetwork => write!(f, "house:card_network"),
+        }
+    }
+}
+
+// Funds a transaction moves from one ledger account to another. The account methods report every transfer they make,
+// so that the ledger is built from what the transactions do rather than from the balances it is checked against.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Transfer<A = Decimal> {
+    pub from: LedgerAccount,
+    pub to: LedgerAccount,
+    pub amount: A,
+}
+
+impl<A> Transfer<A> {
+    pub fn new(from: LedgerAccount, to: LedgerAccount, amount: A) -> Self {
+        Self { from, to, amount }
+    }
+}
+
+// Amounts are signed. Client accounts increase with positive amounts, and the postings of a journal entry always sum to zero.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Posting {
+    pub account: LedgerAccount,
+    pub amount: Decimal,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct JournalEntry {
+    pub id: u64,
+    pub tx: TxId,
+    pub r#type: TransactionType,
+    pub postings: Vec<Posting>,
+}
+
+impl JournalEntry {
+    pub fn is_balanced(&self) -> bool {
+        self.postings.iter().map(|p| p.amount).sum::<Decimal>() == Decimal::ZERO
+    }
+}
+
+// Row of the journal CSV. One row per posting.
+#[derive(Serialize)]
+struct JournalRow<'a> {
+    entry: u64,
+    tx: TxId,
+    r#type: &'a str,
+    account: String,
+    amount: Decimal,
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct Ledger {
+    entries: Vec<JournalEntry>,
+    balances: HashMap<LedgerAccount, Decimal>,
+    // Amounts are written with the scale of the engine.
+    precision: Precision,
+}
+
+impl Ledger {
+    pub fn new(precision: Precision) -> Self {
+        Self {
+            precision,
+            ..Self::default()
+        }
+    }
+
+    pub fn entries(&self) -> &[JournalEntry] {
+        &self.entries
+    }
+
+    pub fn balance(&self, account: LedgerAccount) -> Decimal {
+        self.balances
+            .get(&account)
+            .copied()
+            .unwrap_or(Decimal::ZERO)
+    }
+
+    // Journals the transfers of one transaction. Each transfer is debited from its source and credited to its destination.
+    // Nothing is journaled if the transaction did not move any funds.
+    pub fn record<A: Amount>(
+        &mut self,
+        tx: TxId,
+        r#type: TransactionType,
+        transfers: &[Transfer<A>],
+    ) {
+        let mut postings = Vec::new();
+        for transfer in transfers {
+            let amount = transfer.amount.to_decimal();
+            if amount != Decimal::ZERO {
+                postings.push(Posting {
+                    account: transfer.from,
+                    amount: -amount,
+                });
+                postings.push(Posting {
+                    account: transfer.to,
+                    amount,
+                });
+            }
+        }
+        if postings.is_empty() {
+            return;
+        }
+        for posting in &postings {
+            *self
+                .balances
+                .entry(posting.account)
+                .or_insert(Decimal::ZERO) += posting.amount;
+        }
+        self.entries.push(JournalEntry {
+            id: self.entries.len() as u64,
+            tx,
+            r#type,
+            postings,
+        });
+    }
+
+    // Removes the entries after the first `len` entries and reverts their postings.
+    pub fn truncate(&mut self, len: usize) {
+        for entry in self.entries.drain(len.min(self.entries.len())..) {
+            for posting in entry.postings {
+                *self
+                    .balances
+                    .entry(posting.account)
+                    .or_insert(Decimal::ZERO) -= posting.amount;
+            }
+        }
+    }
+
+    // Checks that every client's available, held and pending balances match the ledger and add up to the total.
+    pub fn verify<A: Amount>(&self, accounts: &Accounts<A>) -> Result<(), EngineError> {
+        for (client, account) in accounts.iter() {
+            let ledger = [
+                (
+                    "available",
+                    account.available.to_decimal(),
+                    LedgerAccount::Available(*client),
+                ),
+                (
+                    "held",
+                    account.held.to_decimal(),
+                    LedgerAccount::Held(*client),
+                ),
+                (
+                    "pending",
+                    account.pending.to_decimal(),
+                    LedgerAccount::Pending(*client),
+                ),
+            ];
+            for (field, balance, ledger_account) in ledger {
+                if balance != self.balance(ledger_account) {
+                    return Err(EngineError::LedgerMismatch {
+                        client: *client,
+                        detail: format!(
+                            "{} is {} but the ledger has {}",
+                            field,
+                            balance,
+                            self.balance(ledger_account)
+                        ),
+                    });
+                }
+            }
+            if account.total != account.available + account.held + account.pending {
+                return Err(EngineError::LedgerMismatch {
+                    client: *client,
+                    detail: format!(
+                        "total {} is not the sum of available, held and pending",
+                        account.total
+                    ),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write_csv<W: io::Write>(&self, wtr: W) -> Result<(), EngineError> {
+        let mut writer = csv::Writer::from_writer(wtr);
+        for entry in &self.entries {
+            for posting in &entry.postings {
+                writer.serialize(JournalRow {
+                    entry: entry.id,
+                    tx: entry.tx,
+                    r#type: entry.r#type.as_str(),
+                    account: posting.account.to_string(),
+                    amount: self.precision.format(posting.amount),
+                })?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounts::AccountsKind;
+    use crate::{Engine, EngineConfig};
+    use pretty_assertions::assert_eq;
+    use rust_decimal_macros::dec;
+    use std::fs::File;
+
+    #[test]
+    fn test_ledger() -> Result<(), EngineError> {
+        let test_file_path = "test_process_records.csv";
+        let test_rdr = File::open(test_file_path)?;
+        let mut engine = Engine::new(EngineConfig {
+            ledger: true,
+            ..EngineConfig::default()
+        });
+        engine.process_records(test_rdr)?;
+        engine.verify_ledger()?;
+        let ledger = engine.ledger().unwrap();
+        assert!(ledger.entries().iter().all(JournalEntry::is_balanced));
+        // The first row is a deposit of 1.0000 by client 1.
+        assert_eq!(
+            ledger.entries()[0].postings,
+            vec![
+                Posting {
+                    account: LedgerAccount::Settlement,
+                    amount: dec!(-1.0000),
+                },
+                Posting {
+                    account: LedgerAccount::Available(1),
+                    amount: dec!(1.0000),
+                },
+            ]
+        );
+        // Client 1 and 2 have been chargebacked 5.0000 in total. Client 3's deposit of 1000.0000 is held.
+        assert_eq!(ledger.balance(LedgerAccount::CardNetwork), dec!(5.0000));
+        assert_eq!(ledger.balance(LedgerAccount::Held(3)), dec!(1000.0000));
+        let totals: Decimal = engine.accounts().values().map(|a| a.total).sum();
+        assert_eq!(
+            ledger.balance(LedgerAccount::Settlement),
+            -totals - dec!(5.0000)
+        );
+
+        // The journal is written with the scale of the engine.
+        let mut engine = Engine::new(EngineConfig {
+            ledger: true,
+            precision: Precision {
+                scale: 2,
+                ..Precision::default()
+            },
+            ..EngineConfig::default()
+        });
+        engine.process_records(File::open(test_file_path)?)?;
+        let mut output = Vec::new();
+        engine.ledger().unwrap().write_csv(&mut output)?;
+        assert!(String::from_utf8(output).unwrap().starts_with(
+            "entry,tx,type,account,amount\n\
+             0,1,deposit,house:settlement,-1.00\n\
+             0,1,deposit,client:1:available,1.00\n"
+        ));
+
+        // The ledger only knows the transfers, so a balance changed without a matching transfer is caught.
+        let mut ledger = Ledger::new(Precision::default());
+        ledger.record(
+            1,
+            TransactionType::Deposit,
+            &[Transfer::new(
+                LedgerAccount::Settlement,
+                LedgerAccount::Available(1),
+                dec!(1.0000),
+            )],
+        );
+        let mut accounts = Accounts::new(AccountsKind::HashMap);
+        let account = accounts.get_or_insert(1);
+        account.available = dec!(2.0000);
+        account.total = dec!(2.0000);
+        assert!(matches!(
+            ledger.verify(&accounts),
+            Err(EngineError::LedgerMismatch { client: 1, .. })
+        ));
+        Ok(())
+    }
+}
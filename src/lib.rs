@@ -1,12 +1,58 @@
-use csv::{ReaderBuilder, StringRecord, Trim};
-use log::{debug, error, info, warn};
+use accounts::{Accounts, AccountsKind};
+use amount::Amount;
+use audit::{AuditMode, Cause, Violation};
+use batch::{undo_queue, BatchRejection, Checkpoint, QueueEntry, QueueUndo};
+use duplicates::{DuplicatePolicy, Fingerprint, Fingerprints};
+use explain::Explanation;
+use history::{History, HistoryFilter, HistoryRecord};
+use ledger::{Ledger, LedgerAccount, Transfer};
+use limits::AmountLimits;
+use log::{error, info, log, warn, Level};
+use precision::Precision;
+use reader::TransactionReader;
+use replay::Cutoff;
 use rust_decimal::Decimal;
+use seen::{SeenIds, SeenIdsKind};
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use simulate::Simulation;
+use statement::Statement;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
 use std::io;
-use std::str::FromStr;
+use std::ops::Range;
+use store::{DepositStore, InMemoryDeposits, SqliteDatabase};
 use thiserror::Error;
 
+pub mod accounts;
+pub mod amount;
+pub mod audit;
+pub mod batch;
+pub mod duplicates;
+pub mod explain;
+pub mod generate;
+pub mod history;
+pub mod ledger;
+pub mod limits;
+pub mod precision;
+pub mod reader;
+pub mod reconcile;
+pub mod replay;
+pub mod seen;
+pub mod simulate;
+pub mod statement;
+pub mod store;
+
+// Client and transaction IDs. The `wide-ids` feature widens both to 64 bits for upstream systems
+// that hand out more than 65,535 clients or 4,294,967,295 transactions.
+#[cfg(not(feature = "wide-ids"))]
+pub type ClientId = u16;
+#[cfg(not(feature = "wide-ids"))]
+pub type TxId = u32;
+#[cfg(feature = "wide-ids")]
+pub type ClientId = u64;
+#[cfg(feature = "wide-ids")]
+pub type TxId = u64;
+
 #[derive(Error, Debug)]
 pub enum EngineError {
     #[error("io error: {0}")]
@@ -18,6 +64,9 @@ pub enum EngineError {
     #[error("Failed to parse string into integer: {0}")]
     ParseIntError(#[from] std::num::ParseIntError),
 
+    #[error("Field is not valid UTF-8: {0}")]
+    Utf8Error(#[from] std::str::Utf8Error),
+
     #[error("Decimal parsing error: {0}")]
     DecimalError(#[from] rust_decimal::Error),
 
@@ -44,6 +93,119 @@ pub enum EngineError {
 
     #[error("Duplicate column `type`")]
     DuplicateColumnType,
+
+    #[error("Duplicate column `batch`")]
+    DuplicateColumnBatch,
+
+    #[error("Ledger does not match the account of client {client}: {detail}")]
+    LedgerMismatch { client: ClientId, detail: String },
+
+    #[error("Audit failed: {0}")]
+    AuditViolation(Box<Violation>),
+
+    #[error("Unknown field `{0}`")]
+    UnknownField(String),
+
+    #[error("sqlite error: {0}")]
+    SqliteError(#[from] rusqlite::Error),
+
+    #[error("Deposit store {0} already exists: remove it or choose another path")]
+    StoreExists(String),
+
+    #[error("Scale of {scale} decimal places is above the {max} places the amount type holds")]
+    ScaleTooLarge { scale: u32, max: u32 },
+}
+
+// Reason a transaction is rejected. A rejected transaction does not change the account.
+#[derive(Error, Clone, PartialEq, Eq, Debug)]
+pub enum Rejection {
+    #[error("Transaction ID is not unique")]
+    DuplicateTx,
+
+    #[error("Transaction ID is already used by a different transaction")]
+    ConflictingTx,
+
+    #[error("Transaction is a replay of an earlier row and has no effect")]
+    Replayed,
+
+    #[error("Amount is not a valid Decimal number")]
+    InvalidAmount,
+
+    #[error("Amount is not positive")]
+    NotPositive,
+
+    #[error("Amount has more than {0} decimal places")]
+    TooPrecise(u32),
+
+    #[error("Amount is zero")]
+    ZeroAmount,
+
+    #[error("Amount is below the minimum of {0}")]
+    BelowMinimum(Decimal),
+
+    #[error("Amount is above the maximum of {0}")]
+    AboveMaximum(Decimal),
+
+    #[error("Amount exceeds the amount it can act on ({0})")]
+    ExceedsLimit(Decimal),
+
+    #[error("Nothing is left for this transaction to act on")]
+    NothingLeft,
+
+    #[error("Account is locked")]
+    AccountLocked,
+
+    #[error("Available funds are not sufficient")]
+    InsufficientFunds,
+
+    #[error("Amount would overflow")]
+    Overflow,
+
+    #[error("Either the tx specified doesn't exist or the specified tx is not a deposit or the specified tx belongs to a different client")]
+    UnknownDeposit,
+
+    #[error("Either the tx specified doesn't exist or the specified tx is not an authorization or the specified tx belongs to a different client")]
+    UnknownAuthorization,
+
+    #[error("Authorization is already {0:?}")]
+    AuthorizationClosed(AuthorizationState),
+
+    #[error("{0}")]
+    InvalidTransition(#[from] InvalidTransition),
+
+    #[error("Transaction type is not specified")]
+    UnknownType,
+
+    #[error("Another transaction of the batch is rejected")]
+    BatchRolledBack,
+
+    #[error("Deposits of the account cannot be read from the deposit store")]
+    DepositStoreFailed,
+}
+
+impl Rejection {
+    // Log level a rejection is reported with.
+    pub fn log_level(&self) -> Level {
+        match self {
+            Rejection::DuplicateTx
+            | Rejection::ConflictingTx
+            | Rejection::Overflow
+            | Rejection::DepositStoreFailed => Level::Error,
+            Rejection::AccountLocked | Rejection::InsufficientFunds | Rejection::Replayed => {
+                Level::Info
+            }
+            Rejection::UnknownDeposit
+            | Rejection::UnknownAuthorization
+            | Rejection::AuthorizationClosed(_)
+            | Rejection::NothingLeft => Level::Debug,
+            _ => Level::Warn,
+        }
+    }
+
+    // A replay of an earlier row has no effect, so it does not roll back its batch.
+    pub fn rolls_back_batch(&self) -> bool {
+        *self != Rejection::Replayed
+    }
 }
 
 pub struct ColumnIndex {
@@ -51,6 +213,14 @@ pub struct ColumnIndex {
     client: usize,
     tx: usize,
     amount: usize,
+    // The batch column is optional.
+    batch: usize,
+}
+
+impl Default for ColumnIndex {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ColumnIndex {
@@ -61,6 +231,7 @@ impl ColumnIndex {
             client: usize::MAX,
             tx: usize::MAX,
             amount: usize::MAX,
+            batch: usize::MAX,
         }
     }
 
@@ -107,365 +278,1833 @@ impl ColumnIndex {
         }
         Ok(())
     }
+
+    pub fn check_duplicate_batch(&self) -> Result<(), EngineError> {
+        if self.batch != usize::MAX {
+            return Err(EngineError::DuplicateColumnBatch);
+        }
+        Ok(())
+    }
+}
+
+// Type of a transaction row. Rows of any other type are rejected.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionType {
+    Deposit,
+    Withdrawal,
+    Authorize,
+    Capture,
+    Void,
+    Settle,
+    Refund,
+    Dispute,
+    Resolve,
+    Chargeback,
+    Representment,
+    #[serde(rename = "prearbitration")]
+    PreArbitration,
+    ArbitrationWon,
+    ArbitrationLost,
+    // An authorization released by the engine once it has expired. It is never read from the input.
+    #[serde(skip_deserializing)]
+    Expire,
+    #[serde(other)]
+    Unknown,
+}
+
+impl TransactionType {
+    // Decodes the type column of a row, which has had its whitespaces removed.
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        match bytes {
+            b"deposit" => TransactionType::Deposit,
+            b"withdrawal" => TransactionType::Withdrawal,
+            b"authorize" => TransactionType::Authorize,
+            b"capture" => TransactionType::Capture,
+            b"void" => TransactionType::Void,
+            b"settle" => TransactionType::Settle,
+            b"refund" => TransactionType::Refund,
+            b"dispute" => TransactionType::Dispute,
+            b"resolve" => TransactionType::Resolve,
+            b"chargeback" => TransactionType::Chargeback,
+            b"representment" => TransactionType::Representment,
+            b"prearbitration" => TransactionType::PreArbitration,
+            b"arbitration_won" => TransactionType::ArbitrationWon,
+            b"arbitration_lost" => TransactionType::ArbitrationLost,
+            _ => TransactionType::Unknown,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransactionType::Deposit => "deposit",
+            TransactionType::Withdrawal => "withdrawal",
+            TransactionType::Authorize => "authorize",
+            TransactionType::Capture => "capture",
+            TransactionType::Void => "void",
+            TransactionType::Settle => "settle",
+            TransactionType::Refund => "refund",
+            TransactionType::Dispute => "dispute",
+            TransactionType::Resolve => "resolve",
+            TransactionType::Chargeback => "chargeback",
+            TransactionType::Representment => "representment",
+            TransactionType::PreArbitration => "prearbitration",
+            TransactionType::ArbitrationWon => "arbitration_won",
+            TransactionType::ArbitrationLost => "arbitration_lost",
+            TransactionType::Expire => "expire",
+            TransactionType::Unknown => "unknown",
+        }
+    }
 }
 
-#[derive(Deserialize, Debug)]
+impl fmt::Display for TransactionType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+#[derive(Clone, Deserialize, Debug)]
 pub struct Transaction {
-    r#type: String,
-    client: u16,
-    tx: u32,
+    r#type: TransactionType,
+    client: ClientId,
+    tx: TxId,
     #[serde(deserialize_with = "csv::invalid_option")]
     amount: Option<Decimal>,
 }
 
-#[derive(PartialEq, Eq, Serialize, Debug)]
+impl Transaction {
+    pub fn new(
+        r#type: TransactionType,
+        client: ClientId,
+        tx: TxId,
+        amount: Option<Decimal>,
+    ) -> Self {
+        Self {
+            r#type,
+            client,
+            tx,
+            amount,
+        }
+    }
+
+    pub fn r#type(&self) -> TransactionType {
+        self.r#type
+    }
+
+    pub fn client(&self) -> ClientId {
+        self.client
+    }
+
+    pub fn tx(&self) -> TxId {
+        self.tx
+    }
+
+    pub fn amount(&self) -> Option<Decimal> {
+        self.amount
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Debug)]
 pub enum DepositState {
+    // The deposit has not settled yet. Its funds are pending and cannot be withdrawn or disputed.
+    Pending,
     NotDisputed,
     Disputed,
     Chargebacked,
+    // The merchant has contested the chargeback and the chargebacked funds have been credited back to the client.
+    Represented,
+    // The card issuer has rejected the representment with a second chargeback. The represented funds are held until arbitration.
+    PreArbitration,
+    // Final states after the card network's arbitration ruling.
+    ArbitrationWon,
+    ArbitrationLost,
     // The engine assumes that a client can dispute a transaction that's already been disputed and resolved.
     // The engine will ignore a dispute when the corresponding transaction is already under dispute.
-    // Once a transaction's been chargebacked, no dispute/resolve/chargeback can be made against the transaction.
+    // Once a transaction's been chargebacked, only a representment can be made against the transaction.
+}
+
+// Events of the dispute lifecycle. Each one is sent as its own transaction type referencing a deposit tx.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DisputeEvent {
+    // Settlement of a pending deposit. Sent as a settle transaction or triggered by the settlement delay.
+    Settle,
+    // A merchant-initiated refund of part or all of the deposit.
+    Refund,
+    Dispute,
+    Resolve,
+    Chargeback,
+    Representment,
+    PreArbitration,
+    ArbitrationWon,
+    ArbitrationLost,
+}
+
+#[derive(Error, Clone, Copy, Debug, PartialEq, Eq)]
+#[error("{event:?} is not allowed for a deposit in state {state:?}")]
+pub struct InvalidTransition {
+    pub state: DepositState,
+    pub event: DisputeEvent,
+}
+
+impl DepositState {
+    // The dispute lifecycle state machine. Every transition that is not listed here is invalid.
+    //
+    // Pending --settle--> NotDisputed
+    // NotDisputed --refund--> NotDisputed, Disputed --refund--> Disputed
+    // NotDisputed --dispute--> Disputed --resolve--> NotDisputed
+    //                          Disputed --chargeback--> Chargebacked --representment--> Represented
+    // Represented --prearbitration--> PreArbitration --arbitration_won--> ArbitrationWon
+    //                                 PreArbitration --arbitration_lost--> ArbitrationLost
+    pub fn transition(self, event: DisputeEvent) -> Result<DepositState, InvalidTransition> {
+        match (self, event) {
+            (DepositState::Pending, DisputeEvent::Settle) => Ok(DepositState::NotDisputed),
+            (DepositState::NotDisputed, DisputeEvent::Refund) => Ok(DepositState::NotDisputed),
+            (DepositState::Disputed, DisputeEvent::Refund) => Ok(DepositState::Disputed),
+            (DepositState::NotDisputed, DisputeEvent::Dispute) => Ok(DepositState::Disputed),
+            (DepositState::Disputed, DisputeEvent::Resolve) => Ok(DepositState::NotDisputed),
+            (DepositState::Disputed, DisputeEvent::Chargeback) => Ok(DepositState::Chargebacked),
+            (DepositState::Chargebacked, DisputeEvent::Representment) => {
+                Ok(DepositState::Represented)
+            }
+            (DepositState::Represented, DisputeEvent::PreArbitration) => {
+                Ok(DepositState::PreArbitration)
+            }
+            (DepositState::PreArbitration, DisputeEvent::ArbitrationWon) => {
+                Ok(DepositState::ArbitrationWon)
+            }
+            (DepositState::PreArbitration, DisputeEvent::ArbitrationLost) => {
+                Ok(DepositState::ArbitrationLost)
+            }
+            (state, event) => Err(InvalidTransition { state, event }),
+        }
+    }
 }
 
-#[derive(PartialEq, Eq, Debug)]
-pub struct Deposit {
-    amount: Decimal,
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Deposit<A = Decimal> {
+    amount: A,
+    // Part of the deposit that is currently held by an open dispute. A dispute may cover only part of a deposit.
+    disputed: A,
+    // Part of the deposit reversed by the chargeback. Representment and pre-arbitration act on this amount.
+    charged_back: A,
+    // Part of the deposit returned by refunds. It can no longer be refunded or disputed.
+    refunded: A,
     state: DepositState,
 }
 
-impl Deposit {
-    pub fn new(deposited_amount: Decimal) -> Self {
+impl<A: Amount> Deposit<A> {
+    pub fn new(deposited_amount: A) -> Self {
         Self {
             amount: deposited_amount,
+            disputed: A::ZERO,
+            charged_back: A::ZERO,
+            refunded: A::ZERO,
             state: DepositState::NotDisputed,
         }
     }
+
+    pub fn pending(deposited_amount: A) -> Self {
+        Self {
+            state: DepositState::Pending,
+            ..Self::new(deposited_amount)
+        }
+    }
+
+    pub fn amount(&self) -> A {
+        self.amount
+    }
+
+    pub fn disputed(&self) -> A {
+        self.disputed
+    }
+
+    pub fn charged_back(&self) -> A {
+        self.charged_back
+    }
+
+    pub fn refunded(&self) -> A {
+        self.refunded
+    }
+
+    pub fn state(&self) -> DepositState {
+        self.state
+    }
+}
+
+// Sums over the deposits of an account. `Account::update` and `Account::settle_delayed` keep them up to date on every deposit transition,
+// so that the audit and the unlock on representment do not need every deposit in memory.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct DepositTotals<A = Decimal> {
+    // Sum of the disputed parts of the deposits.
+    disputed: A,
+    // Sum of the amounts of the pending deposits.
+    pending: A,
+    // Number of deposits chargebacked or lost in arbitration.
+    charged_back: u64,
+}
+
+impl<A: Amount> DepositTotals<A> {
+    fn new() -> Self {
+        Self {
+            disputed: A::ZERO,
+            pending: A::ZERO,
+            charged_back: 0,
+        }
+    }
+
+    fn of(deposit: &Deposit<A>) -> Self {
+        Self {
+            disputed: deposit.disputed,
+            pending: if deposit.state == DepositState::Pending {
+                deposit.amount
+            } else {
+                A::ZERO
+            },
+            charged_back: matches!(
+                deposit.state,
+                DepositState::Chargebacked | DepositState::ArbitrationLost
+            ) as u64,
+        }
+    }
+
+    // Totals computed from the deposits themselves.
+    pub fn sum<'a, I: IntoIterator<Item = &'a Deposit<A>>>(deposits: I) -> Self {
+        let mut totals = Self::new();
+        for deposit in deposits {
+            totals.add(&Self::of(deposit));
+        }
+        totals
+    }
+
+    fn add(&mut self, other: &Self) {
+        self.disputed += other.disputed;
+        self.pending += other.pending;
+        self.charged_back += other.charged_back;
+    }
+
+    fn sub(&mut self, other: &Self) {
+        self.disputed -= other.disputed;
+        self.pending -= other.pending;
+        self.charged_back -= other.charged_back;
+    }
+
+    pub fn disputed(&self) -> A {
+        self.disputed
+    }
+
+    pub fn pending(&self) -> A {
+        self.pending
+    }
+
+    pub fn charged_back(&self) -> u64 {
+        self.charged_back
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Debug)]
+pub enum AuthorizationState {
+    Authorized,
+    Captured,
+    Voided,
+    Expired,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Authorization<A = Decimal> {
+    amount: A,
+    // Amount taken by the capture. The rest of the authorized amount has been released.
+    captured: A,
+    state: AuthorizationState,
+}
+
+impl<A: Amount> Authorization<A> {
+    pub fn new(authorized_amount: A) -> Self {
+        Self {
+            amount: authorized_amount,
+            captured: A::ZERO,
+            state: AuthorizationState::Authorized,
+        }
+    }
+
+    pub fn amount(&self) -> A {
+        self.amount
+    }
+
+    pub fn captured(&self) -> A {
+        self.captured
+    }
+
+    pub fn state(&self) -> AuthorizationState {
+        self.state
+    }
+}
+
+// When deposited funds become available.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Settlement {
+    // Deposits are available immediately.
+    #[default]
+    Immediate,
+    // Deposits are pending until a settle transaction or until the given number of rows has been processed.
+    Delayed(u64),
+    // Deposits are pending until a settle transaction.
+    Manual,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct EngineConfig {
+    // Unlock an account when a representment reverses its only outstanding chargeback.
+    pub unlock_on_representment: bool,
+    // Number of rows after which an authorization that has not been captured or voided expires. Authorizations never expire if None.
+    pub auth_expiry: Option<u64>,
+    pub settlement: Settlement,
+    // Journal every change of a client's balances in a double-entry ledger.
+    pub ledger: bool,
+    // Keep every row and its outcome so that it can be queried after processing.
+    pub history: bool,
+    // Check the balance invariants of the accounts. Accounts are not audited if None.
+    pub audit: Option<AuditMode>,
+    // Structure tracking the transaction IDs seen so far.
+    pub seen_ids: SeenIdsKind,
+    // Structure storing the accounts.
+    pub accounts: AccountsKind,
+    // Number of decimal places of the amounts and how input amounts with more places are rounded.
+    pub precision: Precision,
+    // Bounds on the amounts of deposits, withdrawals and authorizations.
+    pub limits: AmountLimits,
+    // How a deposit, withdrawal or authorization reusing a transaction ID is handled.
+    pub duplicates: DuplicatePolicy,
+}
+
+// Deposits, withdrawals and authorizations claim their transaction ID. Other types reference the ID of an earlier row.
+fn claims_id(transaction: &Transaction) -> bool {
+    matches!(
+        transaction.r#type,
+        TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Authorize
+    )
+}
+
+// Returns the amount a transaction referencing an earlier deposit or authorization acts on.
+// If the row has no amount, the whole `limit` is used, e.g. the full undisputed deposit for a dispute and the full disputed amount for a resolve or chargeback.
+// Amounts that are not positive, that are outside the configured limits or that exceed `limit` are rejected.
+fn partial_amount<A: Amount>(
+    data: &Transaction,
+    limit: A,
+    config: &EngineConfig,
+) -> Result<A, Rejection> {
+    if limit <= A::ZERO {
+        return Err(Rejection::NothingLeft);
+    }
+    let mut amount = match data.amount {
+        Some(amount) => amount,
+        None => return Ok(limit),
+    };
+    amount = config.precision.round::<A>(amount)?;
+    config.limits.check(amount)?;
+    // An amount that rounds to zero is not positive either.
+    if amount <= Decimal::ZERO {
+        return Err(Rejection::NotPositive);
+    }
+    // An amount out of the range of `A` exceeds any limit.
+    match A::from_decimal(amount) {
+        Some(amount) if amount <= limit => Ok(amount),
+        _ => Err(Rejection::ExceedsLimit(limit.to_decimal())),
+    }
 }
 
-#[derive(PartialEq, Eq, Serialize, Deserialize, Debug)]
-pub struct Account {
-    client: u16,
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Debug)]
+pub struct Account<A = Decimal> {
+    client: ClientId,
+    available: A,
+    held: A,
+    // Deposits that have not settled yet. Pending funds count towards total but are not available.
+    #[serde(default)]
+    pending: A,
+    total: A,
+    locked: bool,
+    #[serde(skip)]
+    deposited: HashMap<TxId, Deposit<A>>,
+    #[serde(skip)]
+    authorized: HashMap<TxId, Authorization<A>>,
+    #[serde(skip)]
+    deposit_totals: DepositTotals<A>,
+}
+
+// Row of the account output.
+#[derive(Serialize)]
+struct AccountRow {
+    client: ClientId,
     available: Decimal,
     held: Decimal,
+    pending: Decimal,
     total: Decimal,
     locked: bool,
-    #[serde(skip)]
-    deposited: HashMap<u32, Deposit>,
 }
 
-impl Account {
-    pub fn new(client_num: u16) -> Self {
+// Snapshot of a client's balances.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Debug)]
+pub struct Balances {
+    pub available: Decimal,
+    pub held: Decimal,
+    pub pending: Decimal,
+    pub total: Decimal,
+}
+
+impl<A: Amount> From<&Account<A>> for Balances {
+    fn from(account: &Account<A>) -> Self {
+        Self {
+            available: account.available.to_decimal(),
+            held: account.held.to_decimal(),
+            pending: account.pending.to_decimal(),
+            total: account.total.to_decimal(),
+        }
+    }
+}
+
+impl fmt::Display for Balances {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "available {}, held {}, pending {}, total {}",
+            self.available, self.held, self.pending, self.total
+        )
+    }
+}
+
+impl<A: Amount> Account<A> {
+    pub fn new(client_num: ClientId) -> Self {
         Self {
             client: client_num,
-            available: Decimal::ZERO,
-            held: Decimal::ZERO,
-            total: Decimal::ZERO,
+            available: A::ZERO,
+            held: A::ZERO,
+            pending: A::ZERO,
+            total: A::ZERO,
             locked: false,
             deposited: HashMap::new(),
+            authorized: HashMap::new(),
+            deposit_totals: DepositTotals::new(),
         }
     }
 
-    pub fn deposit(&mut self, data: &Transaction, tx_set: &mut HashSet<u32>) {
-        // Transaction IDs are assumed to be globally unique. If a duplicate tx appears, the transaction is ignored.
-        // We are making a strong assumption: if a deposit tx has an invalid decimal amount such as an empty string, it is ignored but the tx ID will still be added to tx_set.
-        // If there is a subsequent new deposit tx with the same ID and a valid decimal amount, this deposit will be ignored due to duplicate tx ID.
-        if tx_set.contains(&data.tx) {
-            error!(
-                "{:?} Transaction ID is not unique. This transaction is ignored.",
-                data
-            );
-            return;
+    pub fn client(&self) -> ClientId {
+        self.client
+    }
+
+    pub fn available(&self) -> A {
+        self.available
+    }
+
+    pub fn held(&self) -> A {
+        self.held
+    }
+
+    pub fn pending(&self) -> A {
+        self.pending
+    }
+
+    pub fn total(&self) -> A {
+        self.total
+    }
+
+    pub fn locked(&self) -> bool {
+        self.locked
+    }
+
+    pub fn deposited(&self, tx: TxId) -> Option<&Deposit<A>> {
+        self.deposited.get(&tx)
+    }
+
+    pub fn deposits(&self) -> impl Iterator<Item = (&TxId, &Deposit<A>)> {
+        self.deposited.iter()
+    }
+
+    pub fn authorization(&self, tx: TxId) -> Option<&Authorization<A>> {
+        self.authorized.get(&tx)
+    }
+
+    pub fn deposit_totals(&self) -> &DepositTotals<A> {
+        &self.deposit_totals
+    }
+
+    // Applies `change`, which may add or move deposit `tx`, and brings the deposit totals up to date.
+    fn track_deposit<T>(&mut self, tx: TxId, change: impl FnOnce(&mut Self) -> T) -> T {
+        let before = self.deposited.get(&tx).map(DepositTotals::of);
+        let result = change(self);
+        if let Some(before) = before {
+            self.deposit_totals.sub(&before);
         }
-        tx_set.insert(data.tx);
-        if let Some(amount) = data.amount {
-            if self.locked {
-                info!("{:?} Account is locked. Deposit failed.", data);
-                return;
-            }
-            if amount < Decimal::ZERO {
-                warn!(
-                    "{:?} Deposit amount is not positive. This transaction is ignored.",
-                    data
-                );
-                return;
-            }
-            let mut deposit_amount: Decimal = amount;
-            // Amount is assumed to have a precision of up to four places.
-            // In case the input amount has a scale larger than 4, we rescale the scaling factor to 4.
-            deposit_amount.rescale(4);
-            if let Some(total_new) = self.total.checked_add(deposit_amount) {
-                if let Some(available_new) = self.available.checked_add(deposit_amount) {
-                    self.total = total_new;
-                    self.available = available_new;
-                    self.deposited.insert(data.tx, Deposit::new(deposit_amount));
-                    return;
-                }
+        if let Some(after) = self.deposited.get(&tx).map(DepositTotals::of) {
+            self.deposit_totals.add(&after);
+        }
+        result
+    }
+
+    // Transaction IDs are assumed to be globally unique. If a duplicate tx appears, the transaction is ignored.
+    fn check_unique(data: &Transaction, tx_set: &mut dyn SeenIds) -> Result<(), Rejection> {
+        if !tx_set.insert(data.tx) {
+            return Err(Rejection::DuplicateTx);
+        }
+        Ok(())
+    }
+
+    // Returns the amount of a deposit, withdrawal or authorization. An amount out of the range of `A` is rejected with `out_of_range`.
+    fn new_amount(
+        &self,
+        data: &Transaction,
+        out_of_range: Rejection,
+        config: &EngineConfig,
+    ) -> Result<A, Rejection> {
+        let mut amount = data.amount.ok_or(Rejection::InvalidAmount)?;
+        if self.locked {
+            return Err(Rejection::AccountLocked);
+        }
+        if amount < Decimal::ZERO {
+            return Err(Rejection::NotPositive);
+        }
+        // Amounts with more places than the scale are rounded or rejected, depending on the configured rounding.
+        amount = config.precision.round::<A>(amount)?;
+        config.limits.check(amount)?;
+        A::from_decimal(amount).ok_or(out_of_range)
+    }
+
+    pub fn deposit(
+        &mut self,
+        data: &Transaction,
+        tx_set: &mut dyn SeenIds,
+        config: &EngineConfig,
+        transfers: &mut Vec<Transfer<A>>,
+    ) -> Result<(), Rejection> {
+        // We are making a strong assumption: if a deposit tx has an invalid decimal amount such as an empty string, it is ignored but the tx ID will still be added to tx_set.
+        // If there is a subsequent new deposit tx with the same ID and a valid decimal amount, this deposit will be ignored due to duplicate tx ID,
+        // unless the duplicate policy lets it replace the earlier row.
+        Self::check_unique(data, tx_set)?;
+        let deposit_amount = self.new_amount(data, Rejection::Overflow, config)?;
+        if config.settlement != Settlement::Immediate {
+            // The deposit lands in pending and becomes available once it settles.
+            if let (Some(total_new), Some(pending_new)) = (
+                self.total.checked_add(deposit_amount),
+                self.pending.checked_add(deposit_amount),
+            ) {
+                self.total = total_new;
+                self.pending = pending_new;
+                self.deposited
+                    .insert(data.tx, Deposit::pending(deposit_amount));
+                transfers.push(Transfer::new(
+                    LedgerAccount::Settlement,
+                    LedgerAccount::Pending(self.client),
+                    deposit_amount,
+                ));
+                return Ok(());
             }
-            error!(
-                "{:?} Amount would overflow. This deposit is not processed.",
-                data
-            );
-            return;
+        } else if let (Some(total_new), Some(available_new)) = (
+            self.total.checked_add(deposit_amount),
+            self.available.checked_add(deposit_amount),
+        ) {
+            self.total = total_new;
+            self.available = available_new;
+            self.deposited.insert(data.tx, Deposit::new(deposit_amount));
+            transfers.push(Transfer::new(
+                LedgerAccount::Settlement,
+                LedgerAccount::Available(self.client),
+                deposit_amount,
+            ));
+            return Ok(());
         }
-        warn!(
-            "{:?} Deposit amount is not a valid Decimal number. Transaction is ignored.",
-            data
-        );
+        Err(Rejection::Overflow)
     }
 
-    pub fn withdrawal(&mut self, data: &Transaction, tx_set: &mut HashSet<u32>) {
-        // Transaction IDs are assumed to be globally unique. If a duplicate tx appears, the transaction is ignored.
+    pub fn withdrawal(
+        &mut self,
+        data: &Transaction,
+        tx_set: &mut dyn SeenIds,
+        config: &EngineConfig,
+        transfers: &mut Vec<Transfer<A>>,
+    ) -> Result<(), Rejection> {
         // We are making a strong assumption: if a wihdrawl tx has an invalid decimal amount such as an empty string, it is ignored but the tx ID will still be added to tx_set.
         // If there is a subsequent new wihdrawl tx with the same ID and a valid decimal amount, this deposit will be ignored due to duplicate tx ID.
-        if tx_set.contains(&data.tx) {
-            error!(
-                "{:?} Transaction ID is not unique. This transaction is ignored.",
-                data
+        Self::check_unique(data, tx_set)?;
+        // An amount too large to represent is more than the available funds.
+        let withdrawal_amount = self.new_amount(data, Rejection::InsufficientFunds, config)?;
+        if self.available < withdrawal_amount {
+            return Err(Rejection::InsufficientFunds);
+        }
+        if let (Some(total_new), Some(available_new)) = (
+            self.total.checked_sub(withdrawal_amount),
+            self.available.checked_sub(withdrawal_amount),
+        ) {
+            // Available and total will only be updated if overflow does not occur in both operations.
+            self.total = total_new;
+            self.available = available_new;
+            transfers.push(Transfer::new(
+                LedgerAccount::Available(self.client),
+                LedgerAccount::Settlement,
+                withdrawal_amount,
+            ));
+            return Ok(());
+        }
+        Err(Rejection::Overflow)
+    }
+
+    // Holds funds for a card authorization. The tx ID of the authorize row is the authorization ID.
+    pub fn authorize(
+        &mut self,
+        data: &Transaction,
+        tx_set: &mut dyn SeenIds,
+        config: &EngineConfig,
+        transfers: &mut Vec<Transfer<A>>,
+    ) -> Result<(), Rejection> {
+        Self::check_unique(data, tx_set)?;
+        let authorized_amount = self.new_amount(data, Rejection::InsufficientFunds, config)?;
+        if self.available < authorized_amount {
+            return Err(Rejection::InsufficientFunds);
+        }
+        if let (Some(available_new), Some(held_new)) = (
+            self.available.checked_sub(authorized_amount),
+            self.held.checked_add(authorized_amount),
+        ) {
+            self.available = available_new;
+            self.held = held_new;
+            self.authorized
+                .insert(data.tx, Authorization::new(authorized_amount));
+            transfers.push(Transfer::new(
+                LedgerAccount::Available(self.client),
+                LedgerAccount::Held(self.client),
+                authorized_amount,
+            ));
+            return Ok(());
+        }
+        Err(Rejection::Overflow)
+    }
+
+    // Looks up the open authorization referenced by a capture or void.
+    fn open_authorization(&mut self, tx: TxId) -> Result<&mut Authorization<A>, Rejection> {
+        let authorization = self
+            .authorized
+            .get_mut(&tx)
+            .ok_or(Rejection::UnknownAuthorization)?;
+        if authorization.state != AuthorizationState::Authorized {
+            return Err(Rejection::AuthorizationClosed(authorization.state));
+        }
+        Ok(authorization)
+    }
+
+    // Finalizes an authorization. A capture may take only part of the authorized amount, the rest is released back to available.
+    pub fn capture(
+        &mut self,
+        data: &Transaction,
+        config: &EngineConfig,
+        transfers: &mut Vec<Transfer<A>>,
+    ) -> Result<(), Rejection> {
+        let (client, available, held, total) = (self.client, self.available, self.held, self.total);
+        let authorization = self.open_authorization(data.tx)?;
+        let captured_amount = partial_amount(data, authorization.amount, config)?;
+        let released_amount = authorization.amount - captured_amount;
+        if let (Some(held_new), Some(total_new), Some(available_new)) = (
+            held.checked_sub(authorization.amount),
+            total.checked_sub(captured_amount),
+            available.checked_add(released_amount),
+        ) {
+            authorization.captured = captured_amount;
+            authorization.state = AuthorizationState::Captured;
+            self.held = held_new;
+            self.total = total_new;
+            self.available = available_new;
+            transfers.push(Transfer::new(
+                LedgerAccount::Held(client),
+                LedgerAccount::Settlement,
+                captured_amount,
+            ));
+            transfers.push(Transfer::new(
+                LedgerAccount::Held(client),
+                LedgerAccount::Available(client),
+                released_amount,
+            ));
+            return Ok(());
+        }
+        Err(Rejection::Overflow)
+    }
+
+    // Releases the authorized funds back to available.
+    fn release_authorization(
+        &mut self,
+        tx: TxId,
+        state: AuthorizationState,
+        transfers: &mut Vec<Transfer<A>>,
+    ) -> Result<(), Rejection> {
+        let (client, available, held) = (self.client, self.available, self.held);
+        let authorization = self.open_authorization(tx)?;
+        if let (Some(available_new), Some(held_new)) = (
+            available.checked_add(authorization.amount),
+            held.checked_sub(authorization.amount),
+        ) {
+            authorization.state = state;
+            transfers.push(Transfer::new(
+                LedgerAccount::Held(client),
+                LedgerAccount::Available(client),
+                authorization.amount,
+            ));
+            self.available = available_new;
+            self.held = held_new;
+            return Ok(());
+        }
+        Err(Rejection::Overflow)
+    }
+
+    pub fn void(
+        &mut self,
+        data: &Transaction,
+        transfers: &mut Vec<Transfer<A>>,
+    ) -> Result<(), Rejection> {
+        self.release_authorization(data.tx, AuthorizationState::Voided, transfers)
+    }
+
+    pub fn expire_authorization(&mut self, tx: TxId, transfers: &mut Vec<Transfer<A>>) {
+        match self.release_authorization(tx, AuthorizationState::Expired, transfers) {
+            Ok(()) => info!(
+                "Authorization {} of client {} has expired. Funds are released.",
+                tx, self.client
+            ),
+            // Captured and voided authorizations are skipped.
+            Err(Rejection::AuthorizationClosed(_)) => {}
+            Err(e) => error!(
+                "Authorization {} of client {} cannot expire: {}.",
+                tx, self.client, e
+            ),
+        }
+    }
+
+    // Looks up the deposit referenced by a settle, refund or dispute lifecycle transaction and checks the transition against the state machine.
+    // Returns the deposit and the state it moves to.
+    fn referenced_deposit(
+        &mut self,
+        tx: TxId,
+        event: DisputeEvent,
+    ) -> Result<(&mut Deposit<A>, DepositState), Rejection> {
+        let deposited = self
+            .deposited
+            .get_mut(&tx)
+            .ok_or(Rejection::UnknownDeposit)?;
+        let next = deposited.state.transition(event)?;
+        Ok((deposited, next))
+    }
+
+    // Moves a pending deposit to available.
+    fn settle_deposit(
+        &mut self,
+        tx: TxId,
+        transfers: &mut Vec<Transfer<A>>,
+    ) -> Result<(), Rejection> {
+        let (client, pending, available) = (self.client, self.pending, self.available);
+        let (deposited, next) = self.referenced_deposit(tx, DisputeEvent::Settle)?;
+        if let (Some(pending_new), Some(available_new)) = (
+            pending.checked_sub(deposited.amount),
+            available.checked_add(deposited.amount),
+        ) {
+            deposited.state = next;
+            transfers.push(Transfer::new(
+                LedgerAccount::Pending(client),
+                LedgerAccount::Available(client),
+                deposited.amount,
+            ));
+            self.pending = pending_new;
+            self.available = available_new;
+            return Ok(());
+        }
+        Err(Rejection::Overflow)
+    }
+
+    pub fn settle(
+        &mut self,
+        data: &Transaction,
+        transfers: &mut Vec<Transfer<A>>,
+    ) -> Result<(), Rejection> {
+        self.settle_deposit(data.tx, transfers)
+    }
+
+    // Settles a pending deposit once its settlement delay has passed. Deposits settled by a settle transaction are skipped.
+    pub fn settle_delayed(&mut self, tx: TxId, transfers: &mut Vec<Transfer<A>>) {
+        match self.track_deposit(tx, |account| account.settle_deposit(tx, transfers)) {
+            Ok(()) | Err(Rejection::InvalidTransition(_)) => {}
+            Err(e) => error!(
+                "Deposit {} of client {} cannot settle: {}.",
+                tx, self.client, e
+            ),
+        }
+    }
+
+    // Returns part or all of a deposit to the payer. Cumulative refunds are capped at the deposited amount.
+    // The part of the deposit that is under dispute cannot be refunded.
+    pub fn refund(
+        &mut self,
+        data: &Transaction,
+        config: &EngineConfig,
+        transfers: &mut Vec<Transfer<A>>,
+    ) -> Result<(), Rejection> {
+        if self.locked {
+            return Err(Rejection::AccountLocked);
+        }
+        let (client, available, total) = (self.client, self.available, self.total);
+        let (deposited, next) = self.referenced_deposit(data.tx, DisputeEvent::Refund)?;
+        let refunded_amount = partial_amount(
+            data,
+            deposited.amount - deposited.refunded - deposited.disputed,
+            config,
+        )?;
+        if available < refunded_amount {
+            return Err(Rejection::InsufficientFunds);
+        }
+        if let (Some(available_new), Some(total_new)) = (
+            available.checked_sub(refunded_amount),
+            total.checked_sub(refunded_amount),
+        ) {
+            deposited.refunded += refunded_amount;
+            deposited.state = next;
+            self.available = available_new;
+            self.total = total_new;
+            transfers.push(Transfer::new(
+                LedgerAccount::Available(client),
+                LedgerAccount::Settlement,
+                refunded_amount,
+            ));
+            return Ok(());
+        }
+        Err(Rejection::Overflow)
+    }
+
+    pub fn dispute(
+        &mut self,
+        data: &Transaction,
+        config: &EngineConfig,
+        transfers: &mut Vec<Transfer<A>>,
+    ) -> Result<(), Rejection> {
+        let (client, available, held) = (self.client, self.available, self.held);
+        let (deposited, next) = self.referenced_deposit(data.tx, DisputeEvent::Dispute)?;
+        // A dispute may cover only part of the deposit. The refunded part cannot be disputed.
+        let disputed_amount = partial_amount(data, deposited.amount - deposited.refunded, config)?;
+        if let (Some(available_new), Some(held_new)) = (
+            available.checked_sub(disputed_amount),
+            held.checked_add(disputed_amount),
+        ) {
+            deposited.disputed = disputed_amount;
+            deposited.state = next;
+            self.available = available_new;
+            self.held = held_new;
+            transfers.push(Transfer::new(
+                LedgerAccount::Available(client),
+                LedgerAccount::Held(client),
+                disputed_amount,
+            ));
+            return Ok(());
+        }
+        Err(Rejection::Overflow)
+    }
+
+    pub fn resolve(
+        &mut self,
+        data: &Transaction,
+        config: &EngineConfig,
+        transfers: &mut Vec<Transfer<A>>,
+    ) -> Result<(), Rejection> {
+        let (client, available, held) = (self.client, self.available, self.held);
+        let (deposited, next) = self.referenced_deposit(data.tx, DisputeEvent::Resolve)?;
+        // A resolve may release only part of the disputed amount. The rest stays under dispute.
+        let resolved_amount = partial_amount(data, deposited.disputed, config)?;
+        if let (Some(available_new), Some(held_new)) = (
+            available.checked_add(resolved_amount),
+            held.checked_sub(resolved_amount),
+        ) {
+            deposited.disputed -= resolved_amount;
+            if deposited.disputed == A::ZERO {
+                // Dispute is considered resolved. The state now updated to NotDisputed.
+                // The engine assumes that a client can dispute a transaction that's already been disputed and resolved.
+                deposited.state = next;
+            }
+            self.available = available_new;
+            self.held = held_new;
+            transfers.push(Transfer::new(
+                LedgerAccount::Held(client),
+                LedgerAccount::Available(client),
+                resolved_amount,
+            ));
+            return Ok(());
+        }
+        Err(Rejection::Overflow)
+    }
+
+    pub fn chargeback(
+        &mut self,
+        data: &Transaction,
+        config: &EngineConfig,
+        transfers: &mut Vec<Transfer<A>>,
+    ) -> Result<(), Rejection> {
+        let (client, available, held, total) = (self.client, self.available, self.held, self.total);
+        let (deposited, next) = self.referenced_deposit(data.tx, DisputeEvent::Chargeback)?;
+        // A chargeback may reverse only part of the disputed amount.
+        // It closes the dispute, so the part that is not reversed is released back to available.
+        let chargeback_amount = partial_amount(data, deposited.disputed, config)?;
+        let released_amount = deposited.disputed - chargeback_amount;
+        if let (Some(held_new), Some(total_new), Some(available_new)) = (
+            held.checked_sub(deposited.disputed),
+            total.checked_sub(chargeback_amount),
+            available.checked_add(released_amount),
+        ) {
+            deposited.disputed = A::ZERO;
+            deposited.charged_back = chargeback_amount;
+            deposited.state = next;
+            self.held = held_new;
+            self.total = total_new;
+            self.available = available_new;
+            // Once a chargeback occurs, the client's account should be immediately frozen.
+            self.locked = true;
+            transfers.push(Transfer::new(
+                LedgerAccount::Held(client),
+                LedgerAccount::CardNetwork,
+                chargeback_amount,
+            ));
+            transfers.push(Transfer::new(
+                LedgerAccount::Held(client),
+                LedgerAccount::Available(client),
+                released_amount,
+            ));
+            return Ok(());
+        }
+        Err(Rejection::Overflow)
+    }
+
+    // The merchant contests the chargeback. The chargebacked funds are credited back to the client.
+    pub fn representment(
+        &mut self,
+        data: &Transaction,
+        config: &EngineConfig,
+        transfers: &mut Vec<Transfer<A>>,
+    ) -> Result<(), Rejection> {
+        let (client, available, total) = (self.client, self.available, self.total);
+        let (deposited, next) = self.referenced_deposit(data.tx, DisputeEvent::Representment)?;
+        if let (Some(available_new), Some(total_new)) = (
+            available.checked_add(deposited.charged_back),
+            total.checked_add(deposited.charged_back),
+        ) {
+            deposited.state = next;
+            transfers.push(Transfer::new(
+                LedgerAccount::CardNetwork,
+                LedgerAccount::Available(client),
+                deposited.charged_back,
+            ));
+            self.available = available_new;
+            self.total = total_new;
+            // The account stays frozen if another deposit has been chargebacked and not represented.
+            // The totals are brought up to date after the row, so they still count this deposit.
+            if config.unlock_on_representment && self.deposit_totals.charged_back == 1 {
+                self.locked = false;
+            }
+            return Ok(());
+        }
+        Err(Rejection::Overflow)
+    }
+
+    // The card issuer rejects the representment with a second chargeback. The represented funds are held until arbitration.
+    pub fn prearbitration(
+        &mut self,
+        data: &Transaction,
+        transfers: &mut Vec<Transfer<A>>,
+    ) -> Result<(), Rejection> {
+        let (client, available, held) = (self.client, self.available, self.held);
+        let (deposited, next) = self.referenced_deposit(data.tx, DisputeEvent::PreArbitration)?;
+        if let (Some(available_new), Some(held_new)) = (
+            available.checked_sub(deposited.charged_back),
+            held.checked_add(deposited.charged_back),
+        ) {
+            deposited.disputed = deposited.charged_back;
+            deposited.state = next;
+            transfers.push(Transfer::new(
+                LedgerAccount::Available(client),
+                LedgerAccount::Held(client),
+                deposited.charged_back,
+            ));
+            self.available = available_new;
+            self.held = held_new;
+            return Ok(());
+        }
+        Err(Rejection::Overflow)
+    }
+
+    // The card network rules in favour of the client. The held funds are released.
+    pub fn arbitration_won(
+        &mut self,
+        data: &Transaction,
+        transfers: &mut Vec<Transfer<A>>,
+    ) -> Result<(), Rejection> {
+        let (client, available, held) = (self.client, self.available, self.held);
+        let (deposited, next) = self.referenced_deposit(data.tx, DisputeEvent::ArbitrationWon)?;
+        if let (Some(available_new), Some(held_new)) = (
+            available.checked_add(deposited.disputed),
+            held.checked_sub(deposited.disputed),
+        ) {
+            transfers.push(Transfer::new(
+                LedgerAccount::Held(client),
+                LedgerAccount::Available(client),
+                deposited.disputed,
+            ));
+            deposited.disputed = A::ZERO;
+            deposited.state = next;
+            self.available = available_new;
+            self.held = held_new;
+            return Ok(());
+        }
+        Err(Rejection::Overflow)
+    }
+
+    // The card network rules in favour of the card issuer. The held funds are reversed and the account is frozen again.
+    pub fn arbitration_lost(
+        &mut self,
+        data: &Transaction,
+        transfers: &mut Vec<Transfer<A>>,
+    ) -> Result<(), Rejection> {
+        let (client, held, total) = (self.client, self.held, self.total);
+        let (deposited, next) = self.referenced_deposit(data.tx, DisputeEvent::ArbitrationLost)?;
+        if let (Some(held_new), Some(total_new)) = (
+            held.checked_sub(deposited.disputed),
+            total.checked_sub(deposited.disputed),
+        ) {
+            transfers.push(Transfer::new(
+                LedgerAccount::Held(client),
+                LedgerAccount::CardNetwork,
+                deposited.disputed,
+            ));
+            deposited.disputed = A::ZERO;
+            deposited.state = next;
+            self.held = held_new;
+            self.total = total_new;
+            self.locked = true;
+            return Ok(());
+        }
+        Err(Rejection::Overflow)
+    }
+
+    // Applies a transaction to the account. A rejected transaction leaves the account unchanged and is logged with the reason.
+    // The funds an applied transaction moves are added to `transfers`.
+    pub fn update(
+        &mut self,
+        data: &Transaction,
+        tx_set: &mut dyn SeenIds,
+        config: &EngineConfig,
+        transfers: &mut Vec<Transfer<A>>,
+    ) -> Result<(), Rejection> {
+        let result = self.track_deposit(data.tx, |account| match data.r#type {
+            TransactionType::Deposit => account.deposit(data, tx_set, config, transfers),
+            TransactionType::Withdrawal => account.withdrawal(data, tx_set, config, transfers),
+            TransactionType::Authorize => account.authorize(data, tx_set, config, transfers),
+            TransactionType::Capture => account.capture(data, config, transfers),
+            TransactionType::Void => account.void(data, transfers),
+            TransactionType::Settle => account.settle(data, transfers),
+            TransactionType::Refund => account.refund(data, config, transfers),
+            TransactionType::Dispute => account.dispute(data, config, transfers),
+            TransactionType::Resolve => account.resolve(data, config, transfers),
+            TransactionType::Chargeback => account.chargeback(data, config, transfers),
+            TransactionType::Representment => account.representment(data, config, transfers),
+            TransactionType::PreArbitration => account.prearbitration(data, transfers),
+            TransactionType::ArbitrationWon => account.arbitration_won(data, transfers),
+            TransactionType::ArbitrationLost => account.arbitration_lost(data, transfers),
+            TransactionType::Expire | TransactionType::Unknown => Err(Rejection::UnknownType),
+        });
+        if let Err(rejection) = &result {
+            log!(
+                rejection.log_level(),
+                "{:?} {}. This transaction is ignored.",
+                data,
+                rejection
             );
-            return;
         }
-        tx_set.insert(data.tx);
-        if let Some(amount) = data.amount {
-            if self.locked {
-                info!("{:?} Account is locked. withdrawal failed.", data);
-                return;
+        result
+    }
+}
+
+pub struct Engine<A: Amount = Decimal> {
+    config: EngineConfig,
+    accounts: Accounts<A>,
+    // stores all transaction IDs that have appeared so far
+    tx_set: Box<dyn SeenIds>,
+    // Fingerprints of the rows that claimed the IDs. Only kept if the duplicate policy needs them.
+    fingerprints: Option<Fingerprints>,
+    // Number of transaction rows applied so far. Authorizations expire against this clock.
+    clock: u64,
+    // Open authorizations in the order they expire: (expires at, client, authorization tx).
+    expiries: VecDeque<QueueEntry>,
+    // Pending deposits in the order they settle: (settles at, client, deposit tx).
+    settlements: VecDeque<QueueEntry>,
+    ledger: Option<Ledger>,
+    // Funds moved by the row or engine event being applied. Reused for every row.
+    transfers: Vec<Transfer<A>>,
+    history: Option<History>,
+    // The first invariant violation found while auditing every transaction.
+    violation: Option<Violation>,
+    // One checkpoint per open batch or simulation, innermost last.
+    checkpoints: Vec<Checkpoint<A>>,
+    // Deposits that are not in use are kept here.
+    deposits: Box<dyn DepositStore<A>>,
+    // The first deposit store failure. Processing stops at the row it happened on.
+    store_error: Option<EngineError>,
+    // Transaction IDs first seen since the deposit store was last written to.
+    unsaved_tx_ids: Vec<TxId>,
+}
+
+impl Engine {
+    pub fn new(config: EngineConfig) -> Self {
+        Self::with_amount(config)
+    }
+}
+
+impl<A: Amount> Engine<A> {
+    // Keeps the balances in the amount type `A`, e.g. `Engine::<Fixed>::with_amount(config)`.
+    pub fn with_amount(config: EngineConfig) -> Self {
+        Self::with_deposit_store(config, Box::new(InMemoryDeposits))
+    }
+
+    // Keeps the deposits in `deposits` rather than in the accounts, except while a row or a batch uses them.
+    pub fn with_deposit_store(config: EngineConfig, deposits: Box<dyn DepositStore<A>>) -> Self {
+        Self {
+            accounts: Accounts::new(config.accounts),
+            tx_set: config.seen_ids.build(),
+            fingerprints: if config.duplicates.is_enabled() {
+                Some(Fingerprints::new())
+            } else {
+                None
+            },
+            clock: 0,
+            expiries: VecDeque::new(),
+            settlements: VecDeque::new(),
+            ledger: if config.ledger {
+                Some(Ledger::new(config.precision.of::<A>()))
+            } else {
+                None
+            },
+            transfers: Vec::new(),
+            history: if config.history {
+                Some(History::new())
+            } else {
+                None
+            },
+            violation: None,
+            checkpoints: Vec::new(),
+            deposits,
+            store_error: None,
+            unsaved_tx_ids: Vec::new(),
+            config,
+        }
+    }
+
+    pub fn accounts(&self) -> &Accounts<A> {
+        &self.accounts
+    }
+
+    pub fn into_accounts(self) -> HashMap<ClientId, Account<A>> {
+        self.accounts.into_map()
+    }
+
+    // Precision every output of the engine writes its amounts with, so that they all have the same number of decimal places.
+    pub fn output_precision(&self) -> Precision {
+        self.config.precision.of::<A>()
+    }
+
+    // Writes the accounts as CSV. Every amount is written with the configured number of decimal places.
+    pub fn write_accounts<W: io::Write>(&self, wtr: W) -> Result<(), EngineError> {
+        let precision = &self.config.precision;
+        let mut writer = csv::Writer::from_writer(wtr);
+        for account in self.accounts.values() {
+            writer.serialize(AccountRow {
+                client: account.client,
+                available: precision.format(account.available),
+                held: precision.format(account.held),
+                pending: precision.format(account.pending),
+                total: precision.format(account.total),
+                locked: account.locked,
+            })?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn ledger(&self) -> Option<&Ledger> {
+        self.ledger.as_ref()
+    }
+
+    // Rows and engine events of a client in the order they were applied. Empty if the history is disabled.
+    pub fn history(&self, client: ClientId) -> Vec<&HistoryRecord> {
+        match &self.history {
+            Some(history) => history.client(client).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    // Rows and engine events referencing a tx in the order they were applied. Empty if the history is disabled.
+    pub fn transaction(&self, tx: TxId) -> Vec<&HistoryRecord> {
+        match &self.history {
+            Some(history) => history.transaction(tx).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn query(&self, filter: &HistoryFilter) -> Vec<&HistoryRecord> {
+        match &self.history {
+            Some(history) => history.query(filter, &self.accounts),
+            None => Vec::new(),
+        }
+    }
+
+    // Statement of a client for a range of rows. Requires the history to be enabled.
+    pub fn statement(&self, client: ClientId, rows: Range<u64>) -> Statement {
+        Statement::new(client, rows, self.history(client), self.output_precision())
+    }
+
+    // Lifecycle of a transaction ID. Requires the history to be enabled.
+    pub fn explain(&self, tx: TxId) -> Explanation<'_> {
+        Explanation::new(tx, self.transaction(tx), self.output_precision())
+    }
+
+    // Checks the account balances against the ledger. Does nothing if the ledger is disabled.
+    pub fn verify_ledger(&self) -> Result<(), EngineError> {
+        match &self.ledger {
+            Some(ledger) => ledger.verify(&self.accounts),
+            None => Ok(()),
+        }
+    }
+
+    pub fn violation(&self) -> Option<&Violation> {
+        self.violation.as_ref()
+    }
+
+    // Returns the first invariant violation found while processing, or checks every account if the audit runs at the end.
+    // Does nothing if the audit is disabled.
+    pub fn audit(&self) -> Result<(), EngineError> {
+        if let Some(violation) = &self.violation {
+            return Err(EngineError::AuditViolation(Box::new(violation.clone())));
+        }
+        if self.config.audit != Some(AuditMode::End) {
+            return Ok(());
+        }
+        let mut clients: Vec<&ClientId> = self.accounts.keys().collect();
+        clients.sort_unstable();
+        for client in clients {
+            let stored = self.deposits.deposits(*client)?;
+            let violation = if stored.is_empty() {
+                Violation::check_all(&self.accounts[client])
+            } else {
+                let mut account = self.accounts[client].clone();
+                for (tx, deposit) in stored {
+                    account.deposited.entry(tx).or_insert(deposit);
+                }
+                Violation::check_all(&account)
+            };
+            if let Some(violation) = violation {
+                return Err(EngineError::AuditViolation(Box::new(violation)));
             }
-            if amount < Decimal::ZERO {
-                warn!(
-                    "{:?} withdrawal amount is not positive. This transaction is ignored.",
-                    data
-                );
-                return;
-            }
-            let mut withdrawal_amount: Decimal = amount;
-            // Amount is assumed to have a precision of up to four places.
-            // In case the input amount has a scale larger than 4, we rescale the scaling factor to 4.
-            withdrawal_amount.rescale(4);
-            if self.available < withdrawal_amount {
-                info!(
-                    "{:?} Available funds are not sufficient. withdrawal failed.",
-                    data
-                );
-                return;
-            }
-            if let Some(total_new) = self.total.checked_sub(withdrawal_amount) {
-                if let Some(available_new) = self.available.checked_sub(withdrawal_amount) {
-                    // Available and total will only be updated if overflow does not occur in both operations.
-                    self.total = total_new;
-                    self.available = available_new;
-                    return;
+        }
+        Ok(())
+    }
+
+    // Resumes from the accounts and transaction IDs saved in the database by earlier runs, and saves every row and batch to it.
+    // The clock and the queues of pending expiries and settlements are not saved, so authorizations held or deposits pending
+    // when a run ends would never be released. The CLI does not combine `--db` with `--auth-expiry` or `--settlement-delay`.
+    pub fn with_database(
+        config: EngineConfig,
+        database: SqliteDatabase,
+    ) -> Result<Self, EngineError> {
+        let (accounts, tx_ids) = database.load()?;
+        let mut engine = Self::with_deposit_store(config, Box::new(database));
+        for (client, account) in accounts {
+            engine.accounts.insert(client, account);
+        }
+        for tx in tx_ids {
+            engine.tx_set.insert(tx);
+        }
+        Ok(engine)
+    }
+
+    // Brings every deposit back from the deposit store, e.g. to dump the state or to query the history by deposit state.
+    pub fn load_deposits(&mut self) -> Result<(), EngineError> {
+        for (client, account) in self.accounts.iter_mut() {
+            for (tx, deposit) in self.deposits.deposits(client)? {
+                account.deposited.entry(tx).or_insert(deposit);
+            }
+        }
+        Ok(())
+    }
+
+    // Loads the deposit with the referenced tx from the deposit store. Checks over every deposit use the deposit totals of the account.
+    fn load_account_deposits(&mut self, client: ClientId, tx: TxId) -> Result<(), Rejection> {
+        let account = match self.accounts.get_mut(&client) {
+            Some(account) => account,
+            None => return Ok(()),
+        };
+        if account.deposited.contains_key(&tx) {
+            return Ok(());
+        }
+        match self.deposits.deposit(client, tx) {
+            Ok(loaded) => {
+                if let Some(deposit) = loaded {
+                    account.deposited.insert(tx, deposit);
                 }
+                Ok(())
             }
-            error!(
-                "{:?} Amount would overflow. This withdrawal is not processed.",
-                data
-            );
+            Err(error) => {
+                self.store_error.get_or_insert(error);
+                Err(Rejection::DepositStoreFailed)
+            }
+        }
+    }
+
+    // Hands the deposits of the account back to the deposit store. Inside a batch they stay in memory so that the batch can be rolled back.
+    fn evict_deposits(&mut self, client: ClientId) {
+        if !self.checkpoints.is_empty() {
             return;
         }
-        warn!(
-            "{:?} withdrawal amount is not a valid Decimal number. Transaction is ignored.",
-            data
-        );
+        if let Some(mut account) = self.accounts.get_mut(&client) {
+            Self::evict(
+                self.deposits.as_mut(),
+                std::slice::from_mut(&mut account),
+                &mut self.unsaved_tx_ids,
+                &mut self.store_error,
+            );
+        }
     }
 
-    pub fn dispute(&mut self, data: &Transaction) {
-        if let Some(deposited) = self.deposited.get_mut(&data.tx) {
-            match deposited.state {
-                // Check if the tx has been chargebacked. Once a tx's been chargebacked and reversed, no dispute/resolve/chargeback can be made to the tx.
-                DepositState::Chargebacked => {
-                    debug!("{:?} Transaction has already been chargebacked. This dispute request is ignored. ", data);
-                    return;
-                }
-                // Check if the tx is already under dispute. If so, ignore this dispute.
-                DepositState::Disputed => {
-                    debug!("{:?} Transaction is already under dispute. This dispute request is ignored. ", data);
-                    return;
+    fn evict(
+        deposits: &mut dyn DepositStore<A>,
+        accounts: &mut [&mut Account<A>],
+        unsaved_tx_ids: &mut Vec<TxId>,
+        store_error: &mut Option<EngineError>,
+    ) {
+        match deposits.evict(accounts, unsaved_tx_ids) {
+            Ok(()) => unsaved_tx_ids.clear(),
+            Err(error) => {
+                store_error.get_or_insert(error);
+            }
+        }
+    }
+
+    // Whether the balances before a row or engine event are needed. Only the history and the audit of every transaction use them.
+    fn keeps_balances(&self) -> bool {
+        self.history.is_some() || self.config.audit == Some(AuditMode::EveryTransaction)
+    }
+
+    // Keeps the first violation of the account's invariants when auditing every transaction.
+    fn audit_account(
+        config: &EngineConfig,
+        violation: &mut Option<Violation>,
+        account: &Account<A>,
+        cause: impl FnOnce() -> Cause,
+    ) {
+        if config.audit == Some(AuditMode::EveryTransaction) && violation.is_none() {
+            *violation = Violation::check(account, Some(cause()));
+        }
+    }
+
+    // Releases the authorizations that have not been captured or voided within `auth_expiry` rows.
+    fn expire_authorizations(&mut self) {
+        while let Some(&(expires_at, client, tx)) = self.expiries.front() {
+            if expires_at >= self.clock {
+                break;
+            }
+            self.expiries.pop_front();
+            if let Some(checkpoint) = self.checkpoints.last_mut() {
+                checkpoint.save(&self.accounts, client, tx);
+                checkpoint
+                    .expiries
+                    .push(QueueUndo::PushFront((expires_at, client, tx)));
+            }
+            let keeps_balances = self.keeps_balances();
+            if let Some(account) = self.accounts.get_mut(&client) {
+                let before = keeps_balances.then(|| Balances::from(&*account));
+                self.transfers.clear();
+                account.expire_authorization(tx, &mut self.transfers);
+                if let Some(ledger) = &mut self.ledger {
+                    ledger.record(tx, TransactionType::Expire, &self.transfers);
                 }
-                DepositState::NotDisputed => {
-                    if let Some(available_new) = self.available.checked_sub(deposited.amount) {
-                        if let Some(held_new) = self.held.checked_add(deposited.amount) {
-                            self.available = available_new;
-                            self.held = held_new;
-                            deposited.state = DepositState::Disputed;
-                            return;
-                        }
+                if let Some(before) = before {
+                    // Authorizations that have been captured or voided do not expire.
+                    if let (Some(history), false) = (&mut self.history, self.transfers.is_empty()) {
+                        history.record(HistoryRecord::event(
+                            self.clock,
+                            tx,
+                            TransactionType::Expire,
+                            before,
+                            account,
+                        ));
                     }
-                    error!(
-                        "{:?} Amount would overflow. This dispute is not processed.",
-                        data
-                    );
-                    return;
+                    let clock = self.clock;
+                    Self::audit_account(&self.config, &mut self.violation, account, || {
+                        Cause::event(clock, tx, TransactionType::Expire, before)
+                    });
                 }
             }
+            self.evict_deposits(client);
         }
-        debug!("{:?} Either the tx specified doesn't exist or the specified tx is not a deposit or the specified tx belongs to a different client. This tx is ignored.", data);
     }
 
-    pub fn resolve(&mut self, data: &Transaction) {
-        if let Some(deposited) = self.deposited.get_mut(&data.tx) {
-            match deposited.state {
-                // Check if the tx has been chargebacked. Once a tx's been chargebacked and reversed, no dispute/resolve/chargeback can be made to the tx.
-                DepositState::Chargebacked => {
-                    debug!(
-                        "{:?} Transaction has already been chargebacked. This resolve is ignored. ",
-                        data
-                    );
-                    return;
+    // Settles the pending deposits whose settlement delay has passed.
+    fn settle_deposits(&mut self) {
+        while let Some(&(settles_at, client, tx)) = self.settlements.front() {
+            if settles_at >= self.clock {
+                break;
+            }
+            self.settlements.pop_front();
+            if let Some(checkpoint) = self.checkpoints.last_mut() {
+                checkpoint.save(&self.accounts, client, tx);
+                checkpoint
+                    .settlements
+                    .push(QueueUndo::PushFront((settles_at, client, tx)));
+            }
+            if self.load_account_deposits(client, tx).is_err() {
+                continue;
+            }
+            let keeps_balances = self.keeps_balances();
+            if let Some(account) = self.accounts.get_mut(&client) {
+                let before = keeps_balances.then(|| Balances::from(&*account));
+                self.transfers.clear();
+                account.settle_delayed(tx, &mut self.transfers);
+                if let Some(ledger) = &mut self.ledger {
+                    ledger.record(tx, TransactionType::Settle, &self.transfers);
                 }
-                // check if the tx is under dispute. If not, ignore the resolve.
-                DepositState::Disputed => {
-                    if let Some(available_new) = self.available.checked_add(deposited.amount) {
-                        if let Some(held_new) = self.held.checked_sub(deposited.amount) {
-                            self.available = available_new;
-                            self.held = held_new;
-                            // Dispute is considered resolved. The state now updated to NotDisputed.
-                            // The engine assumes that a client can dispute a transaction that's already been disputed and resolved.
-                            deposited.state = DepositState::NotDisputed;
-                            return;
-                        }
+                if let Some(before) = before {
+                    // Deposits settled by a settle row are skipped.
+                    if let (Some(history), false) = (&mut self.history, self.transfers.is_empty()) {
+                        history.record(HistoryRecord::event(
+                            self.clock,
+                            tx,
+                            TransactionType::Settle,
+                            before,
+                            account,
+                        ));
                     }
-                    error!(
-                        "{:?} Amount would overflow. This resolve is not processed.",
-                        data
-                    );
-                    return;
-                }
-                DepositState::NotDisputed => {
-                    debug!(
-                        "{:?} Transaction is not under dispute. This resolve is ignored.",
-                        data
-                    );
-                    return;
+                    let clock = self.clock;
+                    Self::audit_account(&self.config, &mut self.violation, account, || {
+                        Cause::event(clock, tx, TransactionType::Settle, before)
+                    });
                 }
             }
+            self.evict_deposits(client);
         }
-        debug!("{:?} Either the tx specified doesn't exist or the specified tx is not a deposit or the specified tx belongs to a different client. This tx is ignored.", data);
     }
 
-    pub fn chargeback(&mut self, data: &Transaction) {
-        if let Some(deposited) = self.deposited.get_mut(&data.tx) {
-            match deposited.state {
-                // Check if the tx has been chargebacked. Once a tx's been chargebacked and reversed, no dispute/resolve/chargeback can be made to the tx.
-                DepositState::Chargebacked => {
-                    debug!("{:?} Transaction has already been chargebacked. This chargeback request is ignored. ", data
-                    );
-                    return;
+    pub fn apply(&mut self, transaction: &Transaction) -> Result<(), Rejection> {
+        self.expire_authorizations();
+        self.settle_deposits();
+        let new_tx = !self.tx_set.contains(transaction.tx);
+        if let Some(checkpoint) = self.checkpoints.last_mut() {
+            checkpoint.save(&self.accounts, transaction.client, transaction.tx);
+        }
+        if let Err(rejection) = self.load_account_deposits(transaction.client, transaction.tx) {
+            self.clock += 1;
+            return Err(rejection);
+        }
+        // A deposit, withdrawal or authorization reusing an ID is handled by the duplicate policy. Ok if it replaces the earlier row.
+        let reuse = match &self.fingerprints {
+            Some(fingerprints) if !new_tx && claims_id(transaction) => {
+                Some(fingerprints.reuse(transaction, &self.config.duplicates))
+            }
+            _ => None,
+        };
+        // Transactions reference clients. If a client doesn't exist create a new account record.
+        let keeps_balances = self.keeps_balances();
+        let account = self.accounts.get_or_insert(transaction.client);
+        let before = keeps_balances.then(|| Balances::from(&*account));
+        self.transfers.clear();
+        let result = match &reuse {
+            Some(Err(rejection)) => {
+                log!(
+                    rejection.log_level(),
+                    "{:?} {}. This transaction is ignored.",
+                    transaction,
+                    rejection
+                );
+                Err(rejection.clone())
+            }
+            Some(Ok(())) => {
+                self.tx_set.remove(transaction.tx);
+                account.update(
+                    transaction,
+                    self.tx_set.as_mut(),
+                    &self.config,
+                    &mut self.transfers,
+                )
+            }
+            None => account.update(
+                transaction,
+                self.tx_set.as_mut(),
+                &self.config,
+                &mut self.transfers,
+            ),
+        };
+        if new_tx && self.tx_set.contains(transaction.tx) {
+            self.unsaved_tx_ids.push(transaction.tx);
+            if let Some(checkpoint) = self.checkpoints.last_mut() {
+                checkpoint.tx_ids.push(transaction.tx);
+            }
+        }
+        if let Some(fingerprints) = &mut self.fingerprints {
+            if (new_tx || reuse == Some(Ok(()))) && self.tx_set.contains(transaction.tx) {
+                let previous =
+                    fingerprints.insert(transaction.tx, Fingerprint::new(transaction, &result));
+                if let Some(checkpoint) = self.checkpoints.last_mut() {
+                    checkpoint.fingerprints.push((transaction.tx, previous));
                 }
-                // check if the tx is under dispute. If not, ignore the chargeback.
-                DepositState::Disputed => {
-                    if let Some(held_new) = self.held.checked_sub(deposited.amount) {
-                        if let Some(total_new) = self.total.checked_sub(deposited.amount) {
-                            self.held = held_new;
-                            self.total = total_new;
-                            // A chargeback is the final state of a dispute. The state now updated to Chargebacked.
-                            deposited.state = DepositState::Chargebacked;
-                            // Once a chargeback occurs, the client's account should be immediately frozen.
-                            self.locked = true;
-                            return;
-                        }
-                    }
-                    error!(
-                        "{:?} Amount would overflow. This chargeback is not processed.",
-                        data
-                    );
-                    return;
+            }
+        }
+        if let Some(ledger) = &mut self.ledger {
+            ledger.record(transaction.tx, transaction.r#type, &self.transfers);
+        }
+        if let Some(before) = before {
+            if let Some(history) = &mut self.history {
+                history.record(HistoryRecord::row(
+                    self.clock,
+                    transaction,
+                    result.clone(),
+                    before,
+                    account,
+                ));
+            }
+            let clock = self.clock;
+            Self::audit_account(&self.config, &mut self.violation, account, || {
+                Cause::row(clock, transaction, result.clone(), before)
+            });
+        }
+        if let Some(auth_expiry) = self.config.auth_expiry {
+            if result.is_ok() && transaction.r#type == TransactionType::Authorize {
+                self.expiries.push_back((
+                    self.clock + auth_expiry,
+                    transaction.client,
+                    transaction.tx,
+                ));
+                if let Some(checkpoint) = self.checkpoints.last_mut() {
+                    checkpoint.expiries.push(QueueUndo::PopBack);
                 }
-                DepositState::NotDisputed => {
-                    debug!("{:?} Transaction is not under dispute. This chargeback request is ignored.", data
-                    );
-                    return;
+            }
+        }
+        if let Settlement::Delayed(delay) = self.config.settlement {
+            if result.is_ok() && transaction.r#type == TransactionType::Deposit {
+                self.settlements.push_back((
+                    self.clock + delay,
+                    transaction.client,
+                    transaction.tx,
+                ));
+                if let Some(checkpoint) = self.checkpoints.last_mut() {
+                    checkpoint.settlements.push(QueueUndo::PopBack);
+                }
+            }
+        }
+        self.evict_deposits(transaction.client);
+        self.clock += 1;
+        result
+    }
+
+    // Opens a batch. Until it is committed or rolled back, every change of the engine state can be undone.
+    // Batches can be nested. Committing an inner batch hands its changes to the enclosing one.
+    pub fn begin_batch(&mut self) {
+        self.checkpoints.push(Checkpoint::new(
+            self.ledger.as_ref().map_or(0, |l| l.entries().len()),
+            self.history.as_ref().map_or(0, |h| h.records().len()),
+            self.violation.clone(),
+            self.unsaved_tx_ids.len(),
+        ));
+    }
+
+    pub fn commit_batch(&mut self) {
+        let checkpoint = match self.checkpoints.pop() {
+            Some(checkpoint) => checkpoint,
+            None => return,
+        };
+        match self.checkpoints.last_mut() {
+            Some(outer) => outer.merge(checkpoint),
+            // The batch can no longer be rolled back, so the deposits it touched can leave memory. They are written together.
+            // The touched accounts are taken out of the table while they are written.
+            None => {
+                let mut touched: Vec<Account<A>> = checkpoint
+                    .accounts
+                    .keys()
+                    .filter_map(|client| self.accounts.remove(client))
+                    .collect();
+                let mut accounts: Vec<&mut Account<A>> = touched.iter_mut().collect();
+                Self::evict(
+                    self.deposits.as_mut(),
+                    &mut accounts,
+                    &mut self.unsaved_tx_ids,
+                    &mut self.store_error,
+                );
+                for account in touched {
+                    self.accounts.insert(account.client, account);
                 }
             }
         }
-        debug!("{:?} Either the tx specified doesn't exist or the specified tx is not a deposit or the specified tx belongs to a different client. This tx is ignored.", data);
     }
 
-    pub fn update(&mut self, data: &Transaction, tx_set: &mut HashSet<u32>) {
-        match data.r#type.as_str() {
-            "deposit" => self.deposit(data, tx_set),
-            "withdrawal" => self.withdrawal(data, tx_set),
-            "dispute" => self.dispute(data),
-            "resolve" => self.resolve(data),
-            "chargeback" => self.chargeback(data),
-            _ => warn!(
-                "{:?} Transaction type is not specified. This transaction is ignored.",
-                data
+    // Restores the accounts, transaction IDs, ledger and history as they were when the batch began.
+    // The clock is not restored, so that rows keep their position in the input.
+    pub fn rollback_batch(&mut self) {
+        let mut checkpoint = match self.checkpoints.pop() {
+            Some(checkpoint) => checkpoint,
+            None => return,
+        };
+        checkpoint.restore_accounts(&mut self.accounts);
+        for tx in &checkpoint.tx_ids {
+            self.tx_set.remove(*tx);
+        }
+        if let Some(fingerprints) = &mut self.fingerprints {
+            for (tx, previous) in checkpoint.fingerprints.into_iter().rev() {
+                fingerprints.restore(tx, previous);
+            }
+        }
+        self.unsaved_tx_ids.truncate(checkpoint.unsaved_tx_ids);
+        undo_queue(&mut self.expiries, checkpoint.expiries);
+        undo_queue(&mut self.settlements, checkpoint.settlements);
+        if let Some(ledger) = &mut self.ledger {
+            ledger.truncate(checkpoint.ledger_entries);
+        }
+        if let Some(history) = &mut self.history {
+            history.truncate(checkpoint.history_records);
+        }
+        self.violation = checkpoint.violation;
+    }
+
+    // Applies a hypothetical transaction file and reports how it would change the accounts. The engine state is restored afterwards.
+    // Only the accounts the file touches are saved, so a simulation costs about as much as processing the file.
+    pub fn simulate<R: io::Read>(&mut self, rdr: R) -> Result<Simulation, EngineError> {
+        let clock = self.clock;
+        self.begin_batch();
+        let result = self.process(rdr, None);
+        let simulation = match self.checkpoints.last() {
+            Some(checkpoint) => Simulation::new(
+                &checkpoint.accounts,
+                &self.accounts,
+                self.output_precision(),
             ),
+            None => Simulation::default(),
+        };
+        self.rollback_batch();
+        self.clock = clock;
+        result?;
+        Ok(simulation)
+    }
+
+    // Applies the transactions all or nothing. If one is rejected, the batch is rolled back.
+    pub fn apply_batch(&mut self, transactions: &[Transaction]) -> Result<(), BatchRejection> {
+        self.begin_batch();
+        for (index, transaction) in transactions.iter().enumerate() {
+            match self.apply(transaction) {
+                Err(rejection) if rejection.rolls_back_batch() => {
+                    self.rollback_batch();
+                    return Err(BatchRejection { index, rejection });
+                }
+                _ => {}
+            }
         }
+        self.commit_batch();
+        Ok(())
+    }
+
+    pub fn process_records<R: io::Read>(&mut self, rdr: R) -> Result<(), EngineError> {
+        self.process(rdr, None).map(|_| ())
+    }
+
+    // Processes the input up to and including the cutoff row. Returns whether the cutoff was reached.
+    // A cutoff inside a batch takes effect at the end of the batch, so that the batch is applied or rolled back as a whole.
+    pub fn replay<R: io::Read>(&mut self, rdr: R, cutoff: Cutoff) -> Result<bool, EngineError> {
+        self.process(rdr, Some(cutoff))
     }
-}
 
-pub fn process_records<R: io::Read>(rdr: R) -> Result<HashMap<u16, Account>, EngineError> {
-    // Remove leading and trailing whitespaces
-    let mut reader = ReaderBuilder::new().trim(Trim::All).from_reader(rdr);
-    let headers = reader.headers()?;
-    let mut headers_trimmed = Vec::new();
-    // Remove all whitespaces, including whitespaces within a string.
-    for i in headers {
-        let mut i_ = i.to_string();
-        i_.retain(|c| !c.is_whitespace());
-        headers_trimmed.push(i_);
+    fn process<R: io::Read>(
+        &mut self,
+        rdr: R,
+        cutoff: Option<Cutoff>,
+    ) -> Result<bool, EngineError> {
+        let reached = self.process_rows(rdr, cutoff)?;
+        self.deposits.flush()?;
+        Ok(reached)
     }
 
-    let mut column_index = ColumnIndex::new();
-    for (idx, header) in headers_trimmed.iter().enumerate() {
-        match header.as_str() {
-            "type" => {
-                column_index.check_duplicate_type()?;
-                column_index.r#type = idx;
+    fn process_rows<R: io::Read>(
+        &mut self,
+        rdr: R,
+        cutoff: Option<Cutoff>,
+    ) -> Result<bool, EngineError> {
+        let mut reader = TransactionReader::new(rdr)?;
+        // Consecutive rows with the same non-empty batch value form a batch.
+        let mut current_batch: Option<String> = None;
+        let mut batch_failed = false;
+        let mut cutoff_reached = false;
+        while let Some((transaction, batch)) = reader.next_row()? {
+            if batch != current_batch.as_deref().unwrap_or("") {
+                if current_batch.is_some() && !batch_failed {
+                    self.commit_batch();
+                }
+                if cutoff_reached {
+                    return Ok(true);
+                }
+                current_batch = if batch.is_empty() {
+                    None
+                } else {
+                    self.begin_batch();
+                    Some(batch.to_string())
+                };
+                batch_failed = false;
             }
-            "client" => {
-                column_index.check_duplicate_client()?;
-                column_index.client = idx;
+            let row = self.clock;
+            if batch_failed {
+                let rejection = Rejection::BatchRolledBack;
+                log!(
+                    rejection.log_level(),
+                    "{:?} {}. This transaction is ignored.",
+                    transaction,
+                    rejection
+                );
+                self.clock += 1;
+            } else if let Err(rejection) = self.apply(transaction) {
+                // Rejected transactions are logged and skipped. A rejected transaction rolls back its batch.
+                if let Some(batch) = current_batch
+                    .as_ref()
+                    .filter(|_| rejection.rolls_back_batch())
+                {
+                    warn!(
+                        "Batch {} is rolled back because {:?} is rejected: {}.",
+                        batch, transaction, rejection
+                    );
+                    self.rollback_batch();
+                    batch_failed = true;
+                }
             }
-            "tx" => {
-                column_index.check_duplicate_tx()?;
-                column_index.tx = idx;
+            if let Some(error) = self.store_error.take() {
+                return Err(error);
             }
-            "amount" => {
-                column_index.check_duplicate_amount()?;
-                column_index.amount = idx;
+            if !cutoff_reached && cutoff.is_some_and(|c| c.reached(row, transaction)) {
+                if current_batch.is_none() {
+                    return Ok(true);
+                }
+                cutoff_reached = true;
             }
-            _ => error!("Unexpected column name: {}", header),
-        }
-    }
-    column_index.check_missing()?; // check if type, client, tx and amount columns do exist in the input csv data
-
-    let mut tx_set: HashSet<u32> = HashSet::new(); // stores all transaction IDs that have appeared so far
-    let mut account_map: HashMap<u16, Account> = HashMap::new();
-    let mut records = StringRecord::new();
-    while reader.read_record(&mut records)? {
-        let mut row_trimmed = Vec::new();
-        // Remove all whitespaces, including whitespaces within a string.
-        for fields in &records {
-            let mut fields_ = fields.to_string();
-            fields_.retain(|c| !c.is_whitespace());
-            row_trimmed.push(fields_);
         }
-        let transaction = Transaction {
-            r#type: row_trimmed[column_index.r#type].clone(),
-            client: row_trimmed[column_index.client].parse::<u16>()?,
-            tx: row_trimmed[column_index.tx].parse::<u32>()?,
-            amount: Decimal::from_str(row_trimmed[column_index.amount].as_str()).ok(),
-        };
-        match account_map.get_mut(&transaction.client) {
-            Some(account) => account.update(&transaction, &mut tx_set),
-            None => {
-                // Transactions reference clients. If a client doesn't exist create a new account record.
-                let mut accountnew = Account::new(transaction.client);
-                accountnew.update(&transaction, &mut tx_set);
-                account_map.insert(transaction.client, accountnew);
-            }
+        if current_batch.is_some() && !batch_failed {
+            self.commit_batch();
         }
+        Ok(cutoff_reached)
     }
-    Ok(account_map)
 }
 
-// Parses output csv file to account hashmap. This function is used for unit tests.
-pub fn parse_csv<R: io::Read>(rdr: R) -> Result<HashMap<u16, Account>, EngineError> {
+pub fn process_records<R: io::Read>(rdr: R) -> Result<HashMap<ClientId, Account>, EngineError> {
+    process_records_with_config(rdr, &EngineConfig::default())
+}
+
+pub fn process_records_with_config<R: io::Read>(
+    rdr: R,
+    config: &EngineConfig,
+) -> Result<HashMap<ClientId, Account>, EngineError> {
+    let mut engine = Engine::new(config.clone());
+    engine.process_records(rdr)?;
+    Ok(engine.into_accounts())
+}
+
+// Parses output csv file to account hashmap. This function is used to reconcile account files and in unit tests.
+pub fn parse_csv<R: io::Read>(rdr: R) -> Result<HashMap<ClientId, Account>, EngineError> {
     let mut reader = csv::Reader::from_reader(rdr);
-    let mut account_map: HashMap<u16, Account> = HashMap::new();
+    let mut account_map: HashMap<ClientId, Account> = HashMap::new();
     for record in reader.deserialize() {
         let account: Account = record?;
         account_map.insert(account.client, account);
@@ -490,6 +2129,7 @@ mod tests {
             client: 65535,
             available: dec!(10_000_000_000_000.0000),
             held: Decimal::ZERO,
+            pending: Decimal::ZERO,
             total: dec!(10_000_000_000_000.0000),
             locked: false,
             deposited: HashMap::from([
@@ -497,6 +2137,9 @@ mod tests {
                     4294967294,
                     Deposit {
                         amount: dec!(9_999_999_999_999.9999),
+                        disputed: Decimal::ZERO,
+                        charged_back: Decimal::ZERO,
+                        refunded: Decimal::ZERO,
                         state: DepositState::NotDisputed,
                     },
                 ),
@@ -504,10 +2147,15 @@ mod tests {
                     4294967295,
                     Deposit {
                         amount: dec!(0.0001),
+                        disputed: Decimal::ZERO,
+                        charged_back: Decimal::ZERO,
+                        refunded: Decimal::ZERO,
                         state: DepositState::NotDisputed,
                     },
                 ),
             ]),
+            authorized: HashMap::new(),
+            deposit_totals: DepositTotals::default(),
         };
         assert_eq!(*test_accounts.get(&65535).unwrap(), client65535);
         Ok(())
@@ -522,6 +2170,7 @@ mod tests {
             client: 65535,
             available: dec!(9_999_999_999_999.9999),
             held: Decimal::ZERO,
+            pending: Decimal::ZERO,
             total: dec!(9_999_999_999_999.9999),
             locked: false,
             deposited: HashMap::from([
@@ -529,6 +2178,9 @@ mod tests {
                     4294967292,
                     Deposit {
                         amount: dec!(9_999_999_999_999.9999),
+                        disputed: Decimal::ZERO,
+                        charged_back: Decimal::ZERO,
+                        refunded: Decimal::ZERO,
                         state: DepositState::NotDisputed,
                     },
                 ),
@@ -536,24 +2188,35 @@ mod tests {
                     4294967293,
                     Deposit {
                         amount: dec!(0.0001),
+                        disputed: Decimal::ZERO,
+                        charged_back: Decimal::ZERO,
+                        refunded: Decimal::ZERO,
                         state: DepositState::NotDisputed,
                     },
                 ),
             ]),
+            authorized: HashMap::new(),
+            deposit_totals: DepositTotals::default(),
         };
         let client65534 = Account {
             client: 65534,
             available: dec!(9_999_999_999_999.9999),
             held: Decimal::ZERO,
+            pending: Decimal::ZERO,
             total: dec!(9_999_999_999_999.9999),
             locked: false,
             deposited: HashMap::from([(
                 4294967291,
                 Deposit {
                     amount: dec!(10_000_000_000_000.0000),
+                    disputed: Decimal::ZERO,
+                    charged_back: Decimal::ZERO,
+                    refunded: Decimal::ZERO,
                     state: DepositState::NotDisputed,
                 },
             )]),
+            authorized: HashMap::new(),
+            deposit_totals: DepositTotals::default(),
         };
         assert_eq!(*test_accounts.get(&65535).unwrap(), client65535);
         assert_eq!(*test_accounts.get(&65534).unwrap(), client65534);
@@ -569,6 +2232,7 @@ mod tests {
             client: 1,
             available: dec!(-1.5000),
             held: Decimal::ZERO,
+            pending: Decimal::ZERO,
             total: dec!(-1.5000),
             locked: true,
             deposited: HashMap::from([
@@ -576,6 +2240,9 @@ mod tests {
                     1,
                     Deposit {
                         amount: dec!(1.0000),
+                        disputed: Decimal::ZERO,
+                        charged_back: dec!(1.0000),
+                        refunded: Decimal::ZERO,
                         state: DepositState::Chargebacked,
                     },
                 ),
@@ -583,38 +2250,67 @@ mod tests {
                     3,
                     Deposit {
                         amount: dec!(2.0000),
+                        disputed: Decimal::ZERO,
+                        charged_back: dec!(2.0000),
+                        refunded: Decimal::ZERO,
                         state: DepositState::Chargebacked,
                     },
                 ),
             ]),
+            authorized: HashMap::new(),
+            deposit_totals: DepositTotals {
+                disputed: Decimal::ZERO,
+                pending: Decimal::ZERO,
+                charged_back: 2,
+            },
         };
         let client2 = Account {
             client: 2,
             available: Decimal::ZERO,
             held: Decimal::ZERO,
+            pending: Decimal::ZERO,
             total: Decimal::ZERO,
             locked: true,
             deposited: HashMap::from([(
                 2,
                 Deposit {
                     amount: dec!(2.0000),
+                    disputed: Decimal::ZERO,
+                    charged_back: dec!(2.0000),
+                    refunded: Decimal::ZERO,
                     state: DepositState::Chargebacked,
                 },
             )]),
+            authorized: HashMap::new(),
+            deposit_totals: DepositTotals {
+                disputed: Decimal::ZERO,
+                pending: Decimal::ZERO,
+                charged_back: 1,
+            },
         };
         let client3 = Account {
             client: 3,
             available: Decimal::ZERO,
             held: dec!(1000.0000),
+            pending: Decimal::ZERO,
             total: dec!(1000.0000),
             locked: false,
             deposited: HashMap::from([(
                 8,
                 Deposit {
                     amount: dec!(1000.0000),
+                    disputed: dec!(1000.0000),
+                    charged_back: Decimal::ZERO,
+                    refunded: Decimal::ZERO,
                     state: DepositState::Disputed,
                 },
             )]),
+            authorized: HashMap::new(),
+            deposit_totals: DepositTotals {
+                disputed: dec!(1000.0000),
+                pending: Decimal::ZERO,
+                charged_back: 0,
+            },
         };
         assert_eq!(*test_accounts.get(&1).unwrap(), client1);
         assert_eq!(*test_accounts.get(&2).unwrap(), client2);
@@ -631,23 +2327,26 @@ mod tests {
         let mut output_rdr = File::create(output_file_path)?;
         let bufwrt = BufWriter::new(&mut output_rdr);
         let mut writer = csv::Writer::from_writer(bufwrt);
-        for (_, val) in &test_accounts {
+        for val in test_accounts.values() {
             writer.serialize(val)?;
         }
         writer.flush()?;
         let rdr = File::open(output_file_path)?;
         let bufrdr = BufReader::new(rdr);
         let output_accounts = parse_csv(bufrdr)?;
-        let accounts_true: HashMap<u16, Account> = HashMap::from([
+        let accounts_true: HashMap<ClientId, Account> = HashMap::from([
             (
                 1,
                 Account {
                     client: 1,
                     available: dec!(-1.5000),
                     held: dec!(0.0000),
+                    pending: Decimal::ZERO,
                     total: dec!(-1.5000),
                     locked: true,
                     deposited: HashMap::new(),
+                    authorized: HashMap::new(),
+                    deposit_totals: DepositTotals::default(),
                 },
             ),
             (
@@ -656,9 +2355,12 @@ mod tests {
                     client: 2,
                     available: dec!(0.0000),
                     held: dec!(0.0000),
+                    pending: Decimal::ZERO,
                     total: dec!(0.0000),
                     locked: true,
                     deposited: HashMap::new(),
+                    authorized: HashMap::new(),
+                    deposit_totals: DepositTotals::default(),
                 },
             ),
             (
@@ -667,9 +2369,12 @@ mod tests {
                     client: 3,
                     available: dec!(0.0000),
                     held: dec!(1000.0000),
+                    pending: Decimal::ZERO,
                     total: dec!(1000.0000),
                     locked: false,
                     deposited: HashMap::new(),
+                    authorized: HashMap::new(),
+                    deposit_totals: DepositTotals::default(),
                 },
             ),
         ]);
@@ -677,6 +2382,43 @@ mod tests {
         Ok(())
     }
 
+    #[cfg(feature = "wide-ids")]
+    #[test]
+    fn test_wide_ids() -> Result<(), EngineError> {
+        let input = "type,client,tx,amount\n\
+                     deposit,70000,5000000000,10\n\
+                     deposit,18446744073709551615,18446744073709551615,3\n\
+                     deposit,1,5000000000,1\n\
+                     deposit,1,4294967296,2\n\
+                     dispute,70000,5000000000,\n";
+        let mut outputs = Vec::new();
+        for accounts in [AccountsKind::HashMap, AccountsKind::Dense] {
+            for seen_ids in [SeenIdsKind::HashSet, SeenIdsKind::Roaring] {
+                let mut engine = Engine::new(EngineConfig {
+                    accounts,
+                    seen_ids,
+                    ..EngineConfig::default()
+                });
+                engine.process_records(input.as_bytes())?;
+                let mut output = Vec::new();
+                engine.write_accounts(&mut output)?;
+                let mut lines: Vec<String> = String::from_utf8(output)
+                    .unwrap()
+                    .lines()
+                    .map(str::to_string)
+                    .collect();
+                lines[1..].sort_unstable();
+                outputs.push(lines.join("\n"));
+            }
+        }
+        let expected = "client,available,held,pending,total,locked\n\
+                        1,2.0000,0.0000,0.0000,2.0000,false\n\
+                        18446744073709551615,3.0000,0.0000,0.0000,3.0000,false\n\
+                        70000,0.0000,10.0000,0.0000,10.0000,false";
+        assert_eq!(outputs, vec![expected.to_string(); 4]);
+        Ok(())
+    }
+
     #[test]
     fn test_whitespaces() -> Result<(), EngineError> {
         let test_file_path = "test_whitespaces.csv";
@@ -686,29 +2428,41 @@ mod tests {
             client: 10,
             available: dec!(1.0000),
             held: Decimal::ZERO,
+            pending: Decimal::ZERO,
             total: dec!(1.0000),
             locked: false,
             deposited: HashMap::from([(
                 100,
                 Deposit {
                     amount: dec!(1.0000),
+                    disputed: Decimal::ZERO,
+                    charged_back: Decimal::ZERO,
+                    refunded: Decimal::ZERO,
                     state: DepositState::NotDisputed,
                 },
             )]),
+            authorized: HashMap::new(),
+            deposit_totals: DepositTotals::default(),
         };
         let client20 = Account {
             client: 20,
             available: dec!(2.0000),
             held: Decimal::ZERO,
+            pending: Decimal::ZERO,
             total: dec!(2.0000),
             locked: false,
             deposited: HashMap::from([(
                 200,
                 Deposit {
                     amount: dec!(2.0000),
+                    disputed: Decimal::ZERO,
+                    charged_back: Decimal::ZERO,
+                    refunded: Decimal::ZERO,
                     state: DepositState::NotDisputed,
                 },
             )]),
+            authorized: HashMap::new(),
+            deposit_totals: DepositTotals::default(),
         };
         assert_eq!(*test_accounts.get(&10).unwrap(), client10);
         assert_eq!(*test_accounts.get(&20).unwrap(), client20);
@@ -724,32 +2478,602 @@ mod tests {
             client: 10,
             available: dec!(1.0000),
             held: Decimal::ZERO,
+            pending: Decimal::ZERO,
             total: dec!(1.0000),
             locked: false,
             deposited: HashMap::from([(
                 100,
                 Deposit {
                     amount: dec!(1.0000),
+                    disputed: Decimal::ZERO,
+                    charged_back: Decimal::ZERO,
+                    refunded: Decimal::ZERO,
                     state: DepositState::NotDisputed,
                 },
             )]),
+            authorized: HashMap::new(),
+            deposit_totals: DepositTotals::default(),
         };
         let client20 = Account {
             client: 20,
             available: dec!(2.0000),
             held: Decimal::ZERO,
+            pending: Decimal::ZERO,
             total: dec!(2.0000),
             locked: false,
             deposited: HashMap::from([(
                 200,
                 Deposit {
                     amount: dec!(2.0000),
+                    disputed: Decimal::ZERO,
+                    charged_back: Decimal::ZERO,
+                    refunded: Decimal::ZERO,
                     state: DepositState::NotDisputed,
                 },
             )]),
+            authorized: HashMap::new(),
+            deposit_totals: DepositTotals::default(),
         };
         assert_eq!(*test_accounts.get(&10).unwrap(), client10);
         assert_eq!(*test_accounts.get(&20).unwrap(), client20);
         Ok(())
     }
+
+    #[test]
+    fn test_partial_disputes() -> Result<(), EngineError> {
+        let test_file_path = "test_partial_disputes.csv";
+        let test_rdr = File::open(test_file_path)?;
+        let test_accounts = process_records(test_rdr)?;
+        let client1 = Account {
+            client: 1,
+            available: dec!(10.0000),
+            held: Decimal::ZERO,
+            pending: Decimal::ZERO,
+            total: dec!(10.0000),
+            locked: false,
+            deposited: HashMap::from([(
+                1,
+                Deposit {
+                    amount: dec!(10.0000),
+                    disputed: Decimal::ZERO,
+                    charged_back: Decimal::ZERO,
+                    refunded: Decimal::ZERO,
+                    state: DepositState::NotDisputed,
+                },
+            )]),
+            authorized: HashMap::new(),
+            deposit_totals: DepositTotals::default(),
+        };
+        let client2 = Account {
+            client: 2,
+            available: dec!(4.0000),
+            held: Decimal::ZERO,
+            pending: Decimal::ZERO,
+            total: dec!(4.0000),
+            locked: true,
+            deposited: HashMap::from([(
+                2,
+                Deposit {
+                    amount: dec!(5.0000),
+                    disputed: Decimal::ZERO,
+                    charged_back: dec!(1.0000),
+                    refunded: Decimal::ZERO,
+                    state: DepositState::Chargebacked,
+                },
+            )]),
+            authorized: HashMap::new(),
+            deposit_totals: DepositTotals {
+                disputed: Decimal::ZERO,
+                pending: Decimal::ZERO,
+                charged_back: 1,
+            },
+        };
+        let client3 = Account {
+            client: 3,
+            available: Decimal::ZERO,
+            held: dec!(8.0000),
+            pending: Decimal::ZERO,
+            total: dec!(8.0000),
+            locked: false,
+            deposited: HashMap::from([(
+                3,
+                Deposit {
+                    amount: dec!(8.0000),
+                    disputed: dec!(8.0000),
+                    charged_back: Decimal::ZERO,
+                    refunded: Decimal::ZERO,
+                    state: DepositState::Disputed,
+                },
+            )]),
+            authorized: HashMap::new(),
+            deposit_totals: DepositTotals {
+                disputed: dec!(8.0000),
+                pending: Decimal::ZERO,
+                charged_back: 0,
+            },
+        };
+        assert_eq!(*test_accounts.get(&1).unwrap(), client1);
+        assert_eq!(*test_accounts.get(&2).unwrap(), client2);
+        assert_eq!(*test_accounts.get(&3).unwrap(), client3);
+
+        // A dispute amount that rounds to zero is rejected, so the deposit does not get stuck in Disputed with nothing held.
+        let test_accounts = process_records(
+            "type,client,tx,amount\n\
+             deposit,1,1,10\n\
+             dispute,1,1,0.00001\n\
+             resolve,1,1,\n\
+             chargeback,1,1,\n\
+             dispute,1,1,5\n"
+                .as_bytes(),
+        )?;
+        let client1 = Account {
+            client: 1,
+            available: dec!(5.0000),
+            held: dec!(5.0000),
+            pending: Decimal::ZERO,
+            total: dec!(10.0000),
+            locked: false,
+            deposited: HashMap::from([(
+                1,
+                Deposit {
+                    amount: dec!(10.0000),
+                    disputed: dec!(5.0000),
+                    charged_back: Decimal::ZERO,
+                    refunded: Decimal::ZERO,
+                    state: DepositState::Disputed,
+                },
+            )]),
+            authorized: HashMap::new(),
+            deposit_totals: DepositTotals {
+                disputed: dec!(5.0000),
+                pending: Decimal::ZERO,
+                charged_back: 0,
+            },
+        };
+        assert_eq!(*test_accounts.get(&1).unwrap(), client1);
+        Ok(())
+    }
+
+    fn dispute_lifecycle_accounts(unlocked: bool) -> HashMap<ClientId, Account> {
+        HashMap::from([
+            (
+                1,
+                Account {
+                    client: 1,
+                    available: dec!(10.0000),
+                    held: Decimal::ZERO,
+                    pending: Decimal::ZERO,
+                    total: dec!(10.0000),
+                    locked: !unlocked,
+                    deposited: HashMap::from([(
+                        1,
+                        Deposit {
+                            amount: dec!(10.0000),
+                            disputed: Decimal::ZERO,
+                            charged_back: dec!(10.0000),
+                            refunded: Decimal::ZERO,
+                            state: DepositState::ArbitrationWon,
+                        },
+                    )]),
+                    authorized: HashMap::new(),
+                    deposit_totals: DepositTotals::default(),
+                },
+            ),
+            (
+                2,
+                Account {
+                    client: 2,
+                    available: dec!(3.0000),
+                    held: Decimal::ZERO,
+                    pending: Decimal::ZERO,
+                    total: dec!(3.0000),
+                    locked: true,
+                    deposited: HashMap::from([(
+                        2,
+                        Deposit {
+                            amount: dec!(5.0000),
+                            disputed: Decimal::ZERO,
+                            charged_back: dec!(2.0000),
+                            refunded: Decimal::ZERO,
+                            state: DepositState::ArbitrationLost,
+                        },
+                    )]),
+                    authorized: HashMap::new(),
+                    deposit_totals: DepositTotals {
+                        disputed: Decimal::ZERO,
+                        pending: Decimal::ZERO,
+                        charged_back: 1,
+                    },
+                },
+            ),
+            (
+                3,
+                Account {
+                    client: 3,
+                    available: Decimal::ZERO,
+                    held: dec!(1.0000),
+                    pending: Decimal::ZERO,
+                    total: dec!(1.0000),
+                    locked: false,
+                    deposited: HashMap::from([(
+                        3,
+                        Deposit {
+                            amount: dec!(1.0000),
+                            disputed: dec!(1.0000),
+                            charged_back: Decimal::ZERO,
+                            refunded: Decimal::ZERO,
+                            state: DepositState::Disputed,
+                        },
+                    )]),
+                    authorized: HashMap::new(),
+                    deposit_totals: DepositTotals {
+                        disputed: dec!(1.0000),
+                        pending: Decimal::ZERO,
+                        charged_back: 0,
+                    },
+                },
+            ),
+        ])
+    }
+
+    #[test]
+    fn test_dispute_lifecycle() -> Result<(), EngineError> {
+        let test_file_path = "test_dispute_lifecycle.csv";
+        let test_rdr = File::open(test_file_path)?;
+        let test_accounts = process_records(test_rdr)?;
+        assert_eq!(test_accounts, dispute_lifecycle_accounts(false));
+
+        let test_rdr = File::open(test_file_path)?;
+        let config = EngineConfig {
+            unlock_on_representment: true,
+            ..EngineConfig::default()
+        };
+        let test_accounts = process_records_with_config(test_rdr, &config)?;
+        assert_eq!(test_accounts, dispute_lifecycle_accounts(true));
+        Ok(())
+    }
+
+    #[test]
+    fn test_dispute_transitions() {
+        assert_eq!(
+            DepositState::NotDisputed.transition(DisputeEvent::Dispute),
+            Ok(DepositState::Disputed)
+        );
+        assert_eq!(
+            DepositState::Chargebacked.transition(DisputeEvent::Dispute),
+            Err(InvalidTransition {
+                state: DepositState::Chargebacked,
+                event: DisputeEvent::Dispute,
+            })
+        );
+        assert_eq!(
+            DepositState::ArbitrationLost.transition(DisputeEvent::Representment),
+            Err(InvalidTransition {
+                state: DepositState::ArbitrationLost,
+                event: DisputeEvent::Representment,
+            })
+        );
+    }
+
+    fn authorization_accounts(expired: bool) -> HashMap<ClientId, Account> {
+        let client2_authorization = if expired {
+            Authorization {
+                amount: dec!(5.0000),
+                captured: Decimal::ZERO,
+                state: AuthorizationState::Expired,
+            }
+        } else {
+            Authorization {
+                amount: dec!(5.0000),
+                captured: dec!(5.0000),
+                state: AuthorizationState::Captured,
+            }
+        };
+        let client2_balance = if expired { dec!(6.0000) } else { dec!(1.0000) };
+        HashMap::from([
+            (
+                1,
+                Account {
+                    client: 1,
+                    available: dec!(5.0000),
+                    held: dec!(2.0000),
+                    pending: Decimal::ZERO,
+                    total: dec!(7.0000),
+                    locked: false,
+                    deposited: HashMap::from([(1, Deposit::new(dec!(10.0000)))]),
+                    authorized: HashMap::from([
+                        (
+                            2,
+                            Authorization {
+                                amount: dec!(4.0000),
+                                captured: dec!(3.0000),
+                                state: AuthorizationState::Captured,
+                            },
+                        ),
+                        (
+                            4,
+                            Authorization {
+                                amount: dec!(5.0000),
+                                captured: Decimal::ZERO,
+                                state: AuthorizationState::Voided,
+                            },
+                        ),
+                        (5, Authorization::new(dec!(2.0000))),
+                    ]),
+                    deposit_totals: DepositTotals::default(),
+                },
+            ),
+            (
+                2,
+                Account {
+                    client: 2,
+                    available: client2_balance,
+                    held: Decimal::ZERO,
+                    pending: Decimal::ZERO,
+                    total: client2_balance,
+                    locked: false,
+                    deposited: HashMap::from([
+                        (6, Deposit::new(dec!(5.0000))),
+                        (8, Deposit::new(dec!(1.0000))),
+                    ]),
+                    authorized: HashMap::from([(7, client2_authorization)]),
+                    deposit_totals: DepositTotals::default(),
+                },
+            ),
+        ])
+    }
+
+    #[test]
+    fn test_authorizations() -> Result<(), EngineError> {
+        let test_file_path = "test_authorizations.csv";
+        let test_rdr = File::open(test_file_path)?;
+        let test_accounts = process_records(test_rdr)?;
+        assert_eq!(test_accounts, authorization_accounts(false));
+
+        let test_rdr = File::open(test_file_path)?;
+        let config = EngineConfig {
+            auth_expiry: Some(2),
+            ..EngineConfig::default()
+        };
+        let test_accounts = process_records_with_config(test_rdr, &config)?;
+        assert_eq!(test_accounts, authorization_accounts(true));
+        Ok(())
+    }
+
+    fn settlement_accounts(settled: bool) -> HashMap<ClientId, Account> {
+        let (client2_deposit, client2_available, client2_pending) = if settled {
+            (Deposit::new(dec!(4.0000)), dec!(10.0000), Decimal::ZERO)
+        } else {
+            (Deposit::pending(dec!(4.0000)), dec!(6.0000), dec!(4.0000))
+        };
+        HashMap::from([
+            (
+                1,
+                Account {
+                    client: 1,
+                    available: dec!(9.0000),
+                    held: Decimal::ZERO,
+                    pending: Decimal::ZERO,
+                    total: dec!(9.0000),
+                    locked: false,
+                    deposited: HashMap::from([(1, Deposit::new(dec!(10.0000)))]),
+                    authorized: HashMap::new(),
+                    deposit_totals: DepositTotals::default(),
+                },
+            ),
+            (
+                2,
+                Account {
+                    client: 2,
+                    available: client2_available,
+                    held: Decimal::ZERO,
+                    pending: client2_pending,
+                    total: dec!(10.0000),
+                    locked: false,
+                    deposited: HashMap::from([
+                        (3, client2_deposit),
+                        (4, Deposit::new(dec!(6.0000))),
+                    ]),
+                    authorized: HashMap::new(),
+                    deposit_totals: DepositTotals {
+                        disputed: Decimal::ZERO,
+                        pending: client2_pending,
+                        charged_back: 0,
+                    },
+                },
+            ),
+        ])
+    }
+
+    #[test]
+    fn test_settlement() -> Result<(), EngineError> {
+        let test_file_path = "test_settlement.csv";
+        let test_rdr = File::open(test_file_path)?;
+        let config = EngineConfig {
+            settlement: Settlement::Delayed(2),
+            ..EngineConfig::default()
+        };
+        let test_accounts = process_records_with_config(test_rdr, &config)?;
+        assert_eq!(test_accounts, settlement_accounts(true));
+
+        let test_rdr = File::open(test_file_path)?;
+        let config = EngineConfig {
+            settlement: Settlement::Manual,
+            ..EngineConfig::default()
+        };
+        let test_accounts = process_records_with_config(test_rdr, &config)?;
+        assert_eq!(test_accounts, settlement_accounts(false));
+        Ok(())
+    }
+
+    #[test]
+    fn test_refunds() -> Result<(), EngineError> {
+        let test_file_path = "test_refunds.csv";
+        let test_rdr = File::open(test_file_path)?;
+        let test_accounts = process_records(test_rdr)?;
+        let client1 = Account {
+            client: 1,
+            available: Decimal::ZERO,
+            held: Decimal::ZERO,
+            pending: Decimal::ZERO,
+            total: Decimal::ZERO,
+            locked: false,
+            deposited: HashMap::from([(
+                1,
+                Deposit {
+                    amount: dec!(10.0000),
+                    disputed: Decimal::ZERO,
+                    charged_back: Decimal::ZERO,
+                    refunded: dec!(10.0000),
+                    state: DepositState::NotDisputed,
+                },
+            )]),
+            authorized: HashMap::new(),
+            deposit_totals: DepositTotals::default(),
+        };
+        let client2 = Account {
+            client: 2,
+            available: Decimal::ZERO,
+            held: Decimal::ZERO,
+            pending: Decimal::ZERO,
+            total: Decimal::ZERO,
+            locked: true,
+            deposited: HashMap::from([(
+                2,
+                Deposit {
+                    amount: dec!(5.0000),
+                    disputed: Decimal::ZERO,
+                    charged_back: dec!(2.0000),
+                    refunded: dec!(3.0000),
+                    state: DepositState::Chargebacked,
+                },
+            )]),
+            authorized: HashMap::new(),
+            deposit_totals: DepositTotals {
+                disputed: Decimal::ZERO,
+                pending: Decimal::ZERO,
+                charged_back: 1,
+            },
+        };
+        let client3 = Account {
+            client: 3,
+            available: Decimal::ZERO,
+            held: Decimal::ZERO,
+            pending: Decimal::ZERO,
+            total: Decimal::ZERO,
+            locked: false,
+            deposited: HashMap::from([(
+                3,
+                Deposit {
+                    amount: dec!(4.0000),
+                    disputed: Decimal::ZERO,
+                    charged_back: Decimal::ZERO,
+                    refunded: dec!(1.0000),
+                    state: DepositState::NotDisputed,
+                },
+            )]),
+            authorized: HashMap::new(),
+            deposit_totals: DepositTotals::default(),
+        };
+        assert_eq!(*test_accounts.get(&1).unwrap(), client1);
+        assert_eq!(*test_accounts.get(&2).unwrap(), client2);
+        assert_eq!(*test_accounts.get(&3).unwrap(), client3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_batches() -> Result<(), EngineError> {
+        let test_file_path = "test_batches.csv";
+        let test_rdr = File::open(test_file_path)?;
+        let mut engine = Engine::new(EngineConfig {
+            ledger: true,
+            history: true,
+            audit: Some(AuditMode::EveryTransaction),
+            ..EngineConfig::default()
+        });
+        engine.process_records(test_rdr)?;
+        // Batch b is rolled back by the withdrawal of client 2, so client 3 is created by batch c and tx 4 and tx 10 can be reused.
+        // Batch d is rolled back by the withdrawal of client 1, so tx 8 can be reused.
+        let client1 = Account {
+            client: 1,
+            available: dec!(-3.0000),
+            held: dec!(10.0000),
+            pending: Decimal::ZERO,
+            total: dec!(7.0000),
+            locked: false,
+            deposited: HashMap::from([
+                (
+                    1,
+                    Deposit {
+                        amount: dec!(10.0000),
+                        disputed: dec!(10.0000),
+                        charged_back: Decimal::ZERO,
+                        refunded: Decimal::ZERO,
+                        state: DepositState::Disputed,
+                    },
+                ),
+                (8, Deposit::new(dec!(1.0000))),
+            ]),
+            authorized: HashMap::new(),
+            deposit_totals: DepositTotals {
+                disputed: dec!(10.0000),
+                pending: Decimal::ZERO,
+                charged_back: 0,
+            },
+        };
+        let client2 = Account {
+            client: 2,
+            available: dec!(6.0000),
+            held: Decimal::ZERO,
+            pending: Decimal::ZERO,
+            total: dec!(6.0000),
+            locked: false,
+            deposited: HashMap::from([
+                (2, Deposit::new(dec!(5.0000))),
+                (10, Deposit::new(dec!(1.0000))),
+            ]),
+            authorized: HashMap::new(),
+            deposit_totals: DepositTotals::default(),
+        };
+        let client3 = Account {
+            client: 3,
+            available: dec!(2.0000),
+            held: Decimal::ZERO,
+            pending: Decimal::ZERO,
+            total: dec!(2.0000),
+            locked: false,
+            deposited: HashMap::from([
+                (4, Deposit::new(dec!(2.0000))),
+                (6, Deposit::new(dec!(1.0000))),
+            ]),
+            authorized: HashMap::new(),
+            deposit_totals: DepositTotals::default(),
+        };
+        assert_eq!(
+            engine.accounts(),
+            &HashMap::from([(1, client1), (2, client2), (3, client3)])
+        );
+        engine.verify_ledger()?;
+        engine.audit()?;
+        // Rows of rolled back batches are not kept in the history.
+        assert_eq!(
+            engine.history(3).iter().map(|r| r.row).collect::<Vec<_>>(),
+            vec![6, 8, 9]
+        );
+
+        let result = engine.apply_batch(&[
+            Transaction::new(TransactionType::Deposit, 4, 11, Some(dec!(1))),
+            Transaction::new(TransactionType::Withdrawal, 4, 12, Some(dec!(2))),
+        ]);
+        assert_eq!(
+            result,
+            Err(BatchRejection {
+                index: 1,
+                rejection: Rejection::InsufficientFunds,
+            })
+        );
+        assert!(!engine.accounts().contains_key(&4));
+        engine.verify_ledger()?;
+        Ok(())
+    }
 }
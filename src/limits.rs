@@ -0,0 +1,142 @@
+use crate::Rejection;
+use rust_decimal::Decimal;
+
+// Bounds on the amount of a row. Amounts are checked after they are rounded to the scale.
+// Rows referencing an earlier deposit or authorization are only checked if they carry an amount.
+// Amounts with more decimal places than the scale are rejected by `Rounding::Reject`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct AmountLimits {
+    // Smallest amount accepted.
+    pub min: Option<Decimal>,
+    // Largest amount accepted.
+    pub max: Option<Decimal>,
+    // Reject zero amounts, including amounts that round to zero. Without it a zero amount is still checked against `min`.
+    pub reject_zero: bool,
+}
+
+impl AmountLimits {
+    pub fn check(&self, amount: Decimal) -> Result<(), Rejection> {
+        if amount.is_zero() && self.reject_zero {
+            return Err(Rejection::ZeroAmount);
+        }
+        if let Some(min) = self.min {
+            if amount < min {
+                return Err(Rejection::BelowMinimum(min));
+            }
+        }
+        if let Some(max) = self.max {
+            if amount > max {
+                return Err(Rejection::AboveMaximum(max));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Engine, EngineConfig, EngineError};
+    use pretty_assertions::assert_eq;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_amount_limits() -> Result<(), EngineError> {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,0\n\
+                     deposit,1,2,0.00001\n\
+                     deposit,1,3,0.5\n\
+                     deposit,1,4,1000001\n\
+                     deposit,1,5,100\n\
+                     withdrawal,1,6,0.99\n\
+                     authorize,1,7,2000000\n\
+                     withdrawal,1,8,50\n";
+        let mut engine = Engine::new(EngineConfig {
+            limits: AmountLimits {
+                min: Some(dec!(1)),
+                max: Some(dec!(1000000)),
+                reject_zero: true,
+            },
+            history: true,
+            ..EngineConfig::default()
+        });
+        engine.process_records(input.as_bytes())?;
+        let results: Vec<_> = engine
+            .history(1)
+            .iter()
+            .map(|record| (record.tx, record.result.clone()))
+            .collect();
+        assert_eq!(
+            results,
+            vec![
+                (1, Err(Rejection::ZeroAmount)),
+                (2, Err(Rejection::ZeroAmount)),
+                (3, Err(Rejection::BelowMinimum(dec!(1)))),
+                (4, Err(Rejection::AboveMaximum(dec!(1000000)))),
+                (5, Ok(())),
+                (6, Err(Rejection::BelowMinimum(dec!(1)))),
+                (7, Err(Rejection::AboveMaximum(dec!(1000000)))),
+                (8, Ok(())),
+            ]
+        );
+        assert_eq!(engine.accounts()[&1].available(), dec!(50));
+
+        // Without limits zero amounts are accepted. A minimum rejects them even without `reject_zero`.
+        assert_eq!(AmountLimits::default().check(Decimal::ZERO), Ok(()));
+        let min = AmountLimits {
+            min: Some(dec!(1)),
+            ..AmountLimits::default()
+        };
+        assert_eq!(
+            min.check(Decimal::ZERO),
+            Err(Rejection::BelowMinimum(dec!(1)))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_referencing_amount_limits() -> Result<(), EngineError> {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,100\n\
+                     refund,1,1,0.00001\n\
+                     refund,1,1,0.5\n\
+                     dispute,1,1,2000\n\
+                     dispute,1,1,10\n\
+                     resolve,1,1,\n\
+                     authorize,1,2,20\n\
+                     capture,1,2,0.5\n\
+                     capture,1,2,5\n";
+        let mut engine = Engine::new(EngineConfig {
+            limits: AmountLimits {
+                min: Some(dec!(1)),
+                max: Some(dec!(1000)),
+                reject_zero: true,
+            },
+            history: true,
+            ..EngineConfig::default()
+        });
+        engine.process_records(input.as_bytes())?;
+        let results: Vec<_> = engine
+            .history(1)
+            .iter()
+            .map(|record| record.result.clone())
+            .collect();
+        // A row without an amount acts on the whole deposit or authorization and is not checked.
+        assert_eq!(
+            results,
+            vec![
+                Ok(()),
+                Err(Rejection::ZeroAmount),
+                Err(Rejection::BelowMinimum(dec!(1))),
+                Err(Rejection::AboveMaximum(dec!(1000))),
+                Ok(()),
+                Ok(()),
+                Ok(()),
+                Err(Rejection::BelowMinimum(dec!(1))),
+                Ok(()),
+            ]
+        );
+        assert_eq!(engine.accounts()[&1].available(), dec!(95));
+        Ok(())
+    }
+}
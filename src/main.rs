@@ -1,10 +1,23 @@
-use clap::{App, Arg};
-use env_logger;
-use payment_engine::process_records;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use log::warn;
+use payment_engine::accounts::AccountsKind;
+use payment_engine::amount::{Amount, Fixed};
+use payment_engine::audit::AuditMode;
+use payment_engine::duplicates::DuplicatePolicy;
+use payment_engine::generate::{Generator, GeneratorConfig};
+use payment_engine::limits::AmountLimits;
+use payment_engine::precision::{Precision, Rounding};
+use payment_engine::reconcile::{Reconciliation, Tolerances};
+use payment_engine::replay::{write_state, Cutoff};
+use payment_engine::seen::SeenIdsKind;
+use payment_engine::store::{SqliteDatabase, SqliteDeposits};
+use payment_engine::{parse_csv, ClientId, Engine, EngineConfig, Settlement, TxId};
+use rust_decimal::Decimal;
 use std::error::Error;
 use std::fs::File;
 use std::io;
-use std::io::BufReader;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
 
 fn main() -> Result<(), Box<dyn Error>> {
     env_logger::init();
@@ -12,23 +25,608 @@ fn main() -> Result<(), Box<dyn Error>> {
         .version("1.0")
         .author("junjun Dong <junjun.dong9@gmail.com>")
         .about("a payments engine that reads transactions, updates client accounts, handles disputes and chargebacks, and then outputs the state of clients accounts")
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(
             Arg::with_name("input-file-path")
                 .help("Enter the input CSV file path")
                 .required(true),
         )
+        .arg(
+            Arg::with_name("unlock-on-representment")
+                .long("unlock-on-representment")
+                .global(true)
+                .help("Unlock an account when a representment reverses its only outstanding chargeback"),
+        )
+        .arg(
+            Arg::with_name("auth-expiry")
+                .long("auth-expiry")
+                .global(true)
+                .takes_value(true)
+                .value_name("ROWS")
+                .help("Release authorizations that are not captured or voided within ROWS rows"),
+        )
+        .arg(
+            Arg::with_name("settlement-delay")
+                .long("settlement-delay")
+                .global(true)
+                .takes_value(true)
+                .value_name("ROWS")
+                .conflicts_with("manual-settlement")
+                .help("Keep deposits pending until a settle transaction or until ROWS rows have been processed"),
+        )
+        .arg(
+            Arg::with_name("manual-settlement")
+                .long("manual-settlement")
+                .global(true)
+                .help("Keep deposits pending until a settle transaction"),
+        )
+        .arg(
+            Arg::with_name("ledger")
+                .long("ledger")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("Journal every balance change in a double-entry ledger, verify it against the accounts and write the journal to PATH"),
+        )
+        .arg(
+            Arg::with_name("audit")
+                .long("audit")
+                .global(true)
+                .help("Check the balance invariants of the account touched by every transaction and report the first violation"),
+        )
+        .arg(
+            Arg::with_name("audit-at-end")
+                .long("audit-at-end")
+                .global(true)
+                .conflicts_with("audit")
+                .help("Check the balance invariants of every account after processing"),
+        )
+        .arg(
+            Arg::with_name("seen-ids")
+                .long("seen-ids")
+                .global(true)
+                .takes_value(true)
+                .value_name("KIND")
+                .possible_values(&["hashset", "roaring"])
+                .help("Structure tracking the transaction IDs seen so far. roaring uses far less memory for large ID spaces"),
+        )
+        .arg(
+            Arg::with_name("scale")
+                .long("scale")
+                .global(true)
+                .takes_value(true)
+                .value_name("PLACES")
+                .help("Number of decimal places of the amounts, at most 28, or 4 with --amount fixed. Defaults to 4"),
+        )
+        .arg(
+            Arg::with_name("rounding")
+                .long("rounding")
+                .global(true)
+                .takes_value(true)
+                .value_name("STRATEGY")
+                .possible_values(&["half-away-from-zero", "half-even", "truncate", "reject"])
+                .help("How amounts with more decimal places than the scale are handled. Defaults to half-away-from-zero"),
+        )
+        .arg(
+            Arg::with_name("min-amount")
+                .long("min-amount")
+                .global(true)
+                .takes_value(true)
+                .value_name("AMOUNT")
+                .help("Reject rows with an amount of less than AMOUNT, including zero"),
+        )
+        .arg(
+            Arg::with_name("max-amount")
+                .long("max-amount")
+                .global(true)
+                .takes_value(true)
+                .value_name("AMOUNT")
+                .help("Reject rows with an amount of more than AMOUNT"),
+        )
+        .arg(
+            Arg::with_name("reject-zero")
+                .long("reject-zero")
+                .global(true)
+                .help("Reject rows with an amount of zero"),
+        )
+        .arg(
+            Arg::with_name("idempotent")
+                .long("idempotent")
+                .global(true)
+                .help("Ignore exact replays of a row and report rows reusing its transaction ID as conflicting"),
+        )
+        .arg(
+            Arg::with_name("replace-invalid")
+                .long("replace-invalid")
+                .global(true)
+                .help("Let a row reuse the transaction ID of an earlier row rejected for an invalid amount"),
+        )
+        .arg(
+            Arg::with_name("accounts")
+                .long("accounts")
+                .global(true)
+                .takes_value(true)
+                .value_name("KIND")
+                .possible_values(&["hashmap", "dense"])
+                .help("Structure storing the accounts. dense is a table indexed by client ID, which is faster when there are many clients"),
+        )
+        .arg(
+            Arg::with_name("until-tx")
+                .long("until-tx")
+                .takes_value(true)
+                .value_name("TX")
+                .conflicts_with("until-line")
+                .help("Stop right after the first row with this transaction ID, or at the end of its batch"),
+        )
+        .arg(
+            Arg::with_name("until-line")
+                .long("until-line")
+                .takes_value(true)
+                .value_name("ROW")
+                .help("Stop right after this row, counted from 0 after the header, or at the end of its batch"),
+        )
+        .arg(
+            Arg::with_name("state")
+                .long("state")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("Write every deposit and authorization with its state to PATH"),
+        )
+        .arg(
+            Arg::with_name("deposit-store")
+                .long("deposit-store")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("Keep the deposits in an SQLite file at PATH rather than in memory. The file is overwritten"),
+        )
+        .arg(
+            Arg::with_name("db")
+                .long("db")
+                .takes_value(true)
+                .value_name("PATH")
+                .conflicts_with_all(&[
+                    "deposit-store",
+                    "ledger",
+                    "idempotent",
+                    "replace-invalid",
+                    "auth-expiry",
+                    "settlement-delay",
+                ])
+                .help("Resume from the accounts and transaction IDs saved in the SQLite database at PATH and save every row to it"),
+        )
+        .arg(
+            Arg::with_name("commit-rows")
+                .long("commit-rows")
+                .takes_value(true)
+                .value_name("ROWS")
+                .requires("db")
+                .help("Number of rows saved to the database in one SQLite transaction, 1 by default. More rows sync the file less often, but a crash loses the uncommitted rows"),
+        )
+        .arg(
+            Arg::with_name("amount")
+                .long("amount")
+                .takes_value(true)
+                .value_name("TYPE")
+                .possible_values(&["decimal", "fixed"])
+                .help("Number type the balances are kept in. fixed is an integer number of ten-thousandths with faster arithmetic. Input amounts are rounded to the scale the same way for both; fixed rejects an addition or subtraction whose exact result does not fit, where decimal rounds it"),
+        )
+        .subcommand(
+            SubCommand::with_name("statement")
+                .about("Writes account statements with running balances and dispute activity")
+                .arg(
+                    Arg::with_name("input-file-path")
+                        .help("Enter the input CSV file path")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("client")
+                        .long("client")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .value_name("ID")
+                        .help("Client to write a statement for. Defaults to every client"),
+                )
+                .arg(
+                    Arg::with_name("from-row")
+                        .long("from-row")
+                        .takes_value(true)
+                        .value_name("ROW")
+                        .help("First row of the period, counted from 0 after the header"),
+                )
+                .arg(
+                    Arg::with_name("to-row")
+                        .long("to-row")
+                        .takes_value(true)
+                        .value_name("ROW")
+                        .help("Row the period ends before. Defaults to the end of the input"),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["csv", "text"])
+                        .default_value("csv")
+                        .help("Format of the statements"),
+                )
+                .arg(
+                    Arg::with_name("output-dir")
+                        .long("output-dir")
+                        .takes_value(true)
+                        .value_name("DIR")
+                        .default_value(".")
+                        .help("Directory the statement_<client> files are written to"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("explain")
+                .about("Prints every row referencing a transaction, why it was applied or ignored and the balances before and after it")
+                .arg(
+                    Arg::with_name("input-file-path")
+                        .help("Enter the input CSV file path")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("tx")
+                        .long("tx")
+                        .takes_value(true)
+                        .value_name("ID")
+                        .required(true)
+                        .help("Transaction ID to explain"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("simulate")
+                .about("Processes the input, then applies a hypothetical transaction file on a fork and writes the accounts it would change to std out")
+                .arg(
+                    Arg::with_name("input-file-path")
+                        .help("Enter the input CSV file path")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("what-if")
+                        .long("what-if")
+                        .takes_value(true)
+                        .value_name("PATH")
+                        .required(true)
+                        .help("Hypothetical transaction CSV file"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("reconcile")
+                .about("Compares an expected account file with an account file or a fresh run and writes the differences to std out")
+                .arg(
+                    Arg::with_name("expected")
+                        .help("Enter the expected account CSV file path")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("actual")
+                        .long("actual")
+                        .takes_value(true)
+                        .value_name("PATH")
+                        .required_unless("input")
+                        .conflicts_with("input")
+                        .help("Account CSV file to compare"),
+                )
+                .arg(
+                    Arg::with_name("input")
+                        .long("input")
+                        .takes_value(true)
+                        .value_name("PATH")
+                        .help("Transaction CSV file to process and compare"),
+                )
+                .arg(
+                    Arg::with_name("tolerance")
+                        .long("tolerance")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .value_name("[FIELD=]AMOUNT")
+                        .help("Largest difference accepted for every amount field, or for one of available, held, pending and total"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("generate")
+                .about("Generates a seeded transaction stream and the accounts it is expected to produce")
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .takes_value(true)
+                        .value_name("PATH")
+                        .help("Transaction CSV file to write. Defaults to std out"),
+                )
+                .arg(
+                    Arg::with_name("expected")
+                        .long("expected")
+                        .takes_value(true)
+                        .value_name("PATH")
+                        .help("Account CSV file to write the expected accounts to"),
+                )
+                .args(&[
+                    generator_arg("seed", "N", "Seed of the random number generator"),
+                    generator_arg("clients", "N", "Number of clients"),
+                    generator_arg("rows", "N", "Number of rows"),
+                    generator_arg("deposit-weight", "N", "Relative weight of deposits among the regular rows"),
+                    generator_arg("withdrawal-weight", "N", "Relative weight of withdrawals among the regular rows"),
+                    generator_arg("dispute-rate", "P", "Probability that a row disputes an earlier deposit"),
+                    generator_arg("close-rate", "P", "Probability that a row closes an open dispute"),
+                    generator_arg("chargeback-rate", "P", "Probability that a dispute is closed with a chargeback rather than a resolve"),
+                    generator_arg("duplicate-rate", "P", "Probability that a row reuses the ID of an earlier transaction"),
+                    generator_arg("malformed-rate", "P", "Probability that a row is malformed"),
+                    generator_arg("whitespace-rate", "P", "Probability that a field is padded with whitespaces"),
+                ]),
+        )
         .get_matches();
+    if let Some(statement_matches) = matches.subcommand_matches("statement") {
+        return write_statements(statement_matches);
+    }
+    if let Some(explain_matches) = matches.subcommand_matches("explain") {
+        return explain(explain_matches);
+    }
+    if let Some(simulate_matches) = matches.subcommand_matches("simulate") {
+        return simulate(simulate_matches);
+    }
+    if let Some(reconcile_matches) = matches.subcommand_matches("reconcile") {
+        return reconcile(reconcile_matches);
+    }
+    if let Some(generate_matches) = matches.subcommand_matches("generate") {
+        return generate(generate_matches);
+    }
+    match matches.value_of("amount") {
+        Some("fixed") => process::<Fixed>(&matches),
+        _ => process::<Decimal>(&matches),
+    }
+}
+
+fn process<A: Amount>(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
     let path = matches.value_of("input-file-path").unwrap();
+    let config = engine_config(matches)?;
+    config.precision.check::<A>()?;
     let rdr = File::open(path)?;
     let bufrdr = BufReader::new(rdr);
     // CSV rows are streamed through structs that implement the Read trait without loading the entire data set upfront in memory.
     // The process_records function is agnostic to concrete data sources which can be CSV files or TCP streams.
-    let accounts = process_records(bufrdr)?;
+    let mut engine = if let Some(db_path) = matches.value_of("db") {
+        let mut database = SqliteDatabase::open(db_path)?;
+        if let Some(rows) = matches.value_of("commit-rows") {
+            database = database.commit_rows(rows.parse::<usize>()?);
+        }
+        Engine::with_database(config, database)?
+    } else if let Some(store_path) = matches.value_of("deposit-store") {
+        Engine::with_deposit_store(config, Box::new(SqliteDeposits::create(store_path)?))
+    } else {
+        Engine::<A>::with_amount(config)
+    };
+    let cutoff = if let Some(tx) = matches.value_of("until-tx") {
+        Some(Cutoff::Tx(tx.parse::<TxId>()?))
+    } else if let Some(row) = matches.value_of("until-line") {
+        Some(Cutoff::Row(row.parse::<u64>()?))
+    } else {
+        None
+    };
+    match cutoff {
+        Some(cutoff) => {
+            if !engine.replay(bufrdr, cutoff)? {
+                warn!("{:?} is not in the input. All rows are processed.", cutoff);
+            }
+        }
+        None => engine.process_records(bufrdr)?,
+    }
+    if let Some(state_path) = matches.value_of("state") {
+        engine.load_deposits()?;
+        write_state(
+            engine.accounts(),
+            &engine.output_precision(),
+            BufWriter::new(File::create(state_path)?),
+        )?;
+    }
+    if let (Some(ledger), Some(ledger_path)) = (engine.ledger(), matches.value_of("ledger")) {
+        engine.verify_ledger()?;
+        ledger.write_csv(BufWriter::new(File::create(ledger_path)?))?;
+    }
 
-    let mut writer = csv::Writer::from_writer(io::stdout());
-    for (_, val) in &accounts {
-        writer.serialize(val)?;
+    engine.write_accounts(io::stdout())?;
+    engine.audit()?;
+    Ok(())
+}
+
+// The scale is checked against `Decimal`, which every subcommand uses. `process` checks it again against its amount type.
+fn engine_config(matches: &ArgMatches) -> Result<EngineConfig, Box<dyn Error>> {
+    let config = EngineConfig {
+        unlock_on_representment: matches.is_present("unlock-on-representment"),
+        auth_expiry: matches
+            .value_of("auth-expiry")
+            .map(str::parse::<u64>)
+            .transpose()?,
+        settlement: if matches.is_present("manual-settlement") {
+            Settlement::Manual
+        } else if let Some(delay) = matches.value_of("settlement-delay") {
+            Settlement::Delayed(delay.parse::<u64>()?)
+        } else {
+            Settlement::Immediate
+        },
+        ledger: matches.is_present("ledger"),
+        audit: if matches.is_present("audit") {
+            Some(AuditMode::EveryTransaction)
+        } else if matches.is_present("audit-at-end") {
+            Some(AuditMode::End)
+        } else {
+            None
+        },
+        seen_ids: match matches.value_of("seen-ids") {
+            Some("roaring") => SeenIdsKind::Roaring,
+            _ => SeenIdsKind::HashSet,
+        },
+        accounts: match matches.value_of("accounts") {
+            Some("dense") => AccountsKind::Dense,
+            _ => AccountsKind::HashMap,
+        },
+        precision: Precision {
+            scale: value_or(matches, "scale", Precision::default().scale)?,
+            rounding: match matches.value_of("rounding") {
+                Some("half-even") => Rounding::HalfEven,
+                Some("truncate") => Rounding::Truncate,
+                Some("reject") => Rounding::Reject,
+                _ => Rounding::HalfAwayFromZero,
+            },
+        },
+        limits: AmountLimits {
+            min: matches
+                .value_of("min-amount")
+                .map(str::parse::<Decimal>)
+                .transpose()?,
+            max: matches
+                .value_of("max-amount")
+                .map(str::parse::<Decimal>)
+                .transpose()?,
+            reject_zero: matches.is_present("reject-zero"),
+        },
+        duplicates: DuplicatePolicy {
+            fingerprints: matches.is_present("idempotent"),
+            replace_invalid: matches.is_present("replace-invalid"),
+        },
+        ..EngineConfig::default()
+    };
+    config.precision.check::<Decimal>()?;
+    Ok(config)
+}
+
+// Statements are built from the transaction history, so the history is always kept for this subcommand.
+fn write_statements(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let path = matches.value_of("input-file-path").unwrap();
+    let config = EngineConfig {
+        history: true,
+        ..engine_config(matches)?
+    };
+    let mut engine = Engine::new(config);
+    engine.process_records(BufReader::new(File::open(path)?))?;
+    engine.audit()?;
+    let from = matches
+        .value_of("from-row")
+        .map(str::parse::<u64>)
+        .transpose()?
+        .unwrap_or(0);
+    let to = matches
+        .value_of("to-row")
+        .map(str::parse::<u64>)
+        .transpose()?
+        .unwrap_or(u64::MAX);
+    let mut clients = match matches.values_of("client") {
+        Some(values) => values
+            .map(str::parse::<ClientId>)
+            .collect::<Result<Vec<_>, _>>()?,
+        None => engine.accounts().keys().copied().collect(),
+    };
+    clients.sort_unstable();
+    let text = matches.value_of("format") == Some("text");
+    let dir = Path::new(matches.value_of("output-dir").unwrap());
+    for client in clients {
+        let statement = engine.statement(client, from..to);
+        let extension = if text { "txt" } else { "csv" };
+        let file = File::create(dir.join(format!("statement_{}.{}", client, extension)))?;
+        if text {
+            statement.write_text(BufWriter::new(file))?;
+        } else {
+            statement.write_csv(BufWriter::new(file))?;
+        }
+    }
+    Ok(())
+}
+
+fn explain(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let path = matches.value_of("input-file-path").unwrap();
+    let tx = matches.value_of("tx").unwrap().parse::<TxId>()?;
+    let config = EngineConfig {
+        history: true,
+        ..engine_config(matches)?
+    };
+    let mut engine = Engine::new(config);
+    engine.process_records(BufReader::new(File::open(path)?))?;
+    engine.explain(tx).write_text(io::stdout())?;
+    Ok(())
+}
+
+fn simulate(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let path = matches.value_of("input-file-path").unwrap();
+    let mut engine = Engine::new(engine_config(matches)?);
+    engine.process_records(BufReader::new(File::open(path)?))?;
+    let what_if = matches.value_of("what-if").unwrap();
+    let simulation = engine.simulate(BufReader::new(File::open(what_if)?))?;
+    simulation.write_csv(io::stdout())?;
+    Ok(())
+}
+
+// Exits with status 1 if the accounts do not reconcile.
+fn reconcile(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let expected = parse_csv(BufReader::new(File::open(
+        matches.value_of("expected").unwrap(),
+    )?))?;
+    let actual = match matches.value_of("actual") {
+        Some(path) => parse_csv(BufReader::new(File::open(path)?))?,
+        None => {
+            let mut engine = Engine::new(engine_config(matches)?);
+            engine.process_records(BufReader::new(File::open(
+                matches.value_of("input").unwrap(),
+            )?))?;
+            engine.audit()?;
+            engine.into_accounts()
+        }
+    };
+    let mut tolerances = Tolerances::default();
+    for spec in matches.values_of("tolerance").into_iter().flatten() {
+        tolerances.set(spec)?;
+    }
+    let reconciliation = Reconciliation::new(&expected, &actual, &tolerances);
+    reconciliation.write_csv(io::stdout())?;
+    if !reconciliation.is_reconciled() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn generator_arg<'a>(name: &'a str, value_name: &'a str, help: &'a str) -> Arg<'a, 'a> {
+    Arg::with_name(name)
+        .long(name)
+        .takes_value(true)
+        .value_name(value_name)
+        .help(help)
+}
+
+// Parses the value of an optional argument, or returns the default if it is absent.
+fn value_or<T>(matches: &ArgMatches, name: &str, default: T) -> Result<T, Box<dyn Error>>
+where
+    T: std::str::FromStr,
+    T::Err: Error + 'static,
+{
+    Ok(matches
+        .value_of(name)
+        .map(str::parse::<T>)
+        .transpose()?
+        .unwrap_or(default))
+}
+
+fn generate(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let default = GeneratorConfig::default();
+    let config = GeneratorConfig {
+        seed: value_or(matches, "seed", default.seed)?,
+        clients: value_or(matches, "clients", default.clients)?,
+        rows: value_or(matches, "rows", default.rows)?,
+        deposit_weight: value_or(matches, "deposit-weight", default.deposit_weight)?,
+        withdrawal_weight: value_or(matches, "withdrawal-weight", default.withdrawal_weight)?,
+        dispute_rate: value_or(matches, "dispute-rate", default.dispute_rate)?,
+        close_rate: value_or(matches, "close-rate", default.close_rate)?,
+        chargeback_rate: value_or(matches, "chargeback-rate", default.chargeback_rate)?,
+        duplicate_rate: value_or(matches, "duplicate-rate", default.duplicate_rate)?,
+        malformed_rate: value_or(matches, "malformed-rate", default.malformed_rate)?,
+        whitespace_rate: value_or(matches, "whitespace-rate", default.whitespace_rate)?,
+    };
+    let mut generator = Generator::new(config);
+    match matches.value_of("output") {
+        Some(path) => generator.write_csv(BufWriter::new(File::create(path)?))?,
+        None => generator.write_csv(io::stdout())?,
+    }
+    if let Some(path) = matches.value_of("expected") {
+        generator.write_accounts(BufWriter::new(File::create(path)?))?;
     }
-    writer.flush()?;
     Ok(())
 }
@@ -0,0 +1,198 @@
+use crate::amount::Amount;
+use crate::{Balances, EngineError, Rejection};
+use rust_decimal::prelude::RoundingStrategy;
+use rust_decimal::Decimal;
+
+// How an input amount with more decimal places than the scale is handled.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Rounding {
+    // Round half away from zero, e.g. 1.00005 to 1.0001 at four places.
+    #[default]
+    HalfAwayFromZero,
+    // Banker's rounding: round half to the even neighbour, e.g. 1.00005 to 1.0000 and 1.00015 to 1.0002.
+    HalfEven,
+    // Drop the extra places, e.g. 1.00009 to 1.0000.
+    Truncate,
+    // Reject the transaction.
+    Reject,
+}
+
+// Number of decimal places amounts are kept and written with, e.g. 2 for a partner sending cents or 8 for one sending satoshis.
+// There is one precision per engine. A precision per currency is not implemented: it needs balances per currency, and a client has a single balance.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Precision {
+    pub scale: u32,
+    pub rounding: Rounding,
+}
+
+impl Default for Precision {
+    fn default() -> Self {
+        Self {
+            scale: 4,
+            rounding: Rounding::HalfAwayFromZero,
+        }
+    }
+}
+
+impl Precision {
+    // Fails if `A` cannot hold the scale, e.g. 6 places with fixed-point amounts, which hold at most four.
+    pub fn check<A: Amount>(&self) -> Result<(), EngineError> {
+        if self.scale > A::MAX_SCALE {
+            return Err(EngineError::ScaleTooLarge {
+                scale: self.scale,
+                max: A::MAX_SCALE,
+            });
+        }
+        Ok(())
+    }
+
+    // Number of decimal places of the amount type `A`. An engine built without `check` uses at most the places `A` holds.
+    pub fn scale<A: Amount>(&self) -> u32 {
+        self.scale.min(A::MAX_SCALE)
+    }
+
+    // Brings an input amount to the scale of `A`, padding it with zeros or rounding it.
+    pub fn round<A: Amount>(&self, mut amount: Decimal) -> Result<Decimal, Rejection> {
+        let scale = self.scale::<A>();
+        if amount.scale() > scale {
+            amount = match self.rounding {
+                Rounding::HalfAwayFromZero => {
+                    amount.round_dp_with_strategy(scale, RoundingStrategy::MidpointAwayFromZero)
+                }
+                Rounding::HalfEven => {
+                    amount.round_dp_with_strategy(scale, RoundingStrategy::MidpointNearestEven)
+                }
+                Rounding::Truncate => {
+                    amount.round_dp_with_strategy(scale, RoundingStrategy::ToZero)
+                }
+                // Trailing zeros are not extra precision.
+                Rounding::Reject if amount.normalize().scale() > scale => {
+                    return Err(Rejection::TooPrecise(scale))
+                }
+                Rounding::Reject => amount.normalize(),
+            };
+        }
+        amount.rescale(scale);
+        Ok(amount)
+    }
+
+    // The precision with the scale of `A`. Outputs built from amounts that have been converted to `Decimal` are written with it.
+    pub fn of<A: Amount>(&self) -> Self {
+        Self {
+            scale: self.scale::<A>(),
+            rounding: self.rounding,
+        }
+    }
+
+    // Writes an amount of `A` with exactly the scale's number of places, so that every amount of every output has the same format.
+    pub fn format<A: Amount>(&self, amount: A) -> Decimal {
+        let mut amount = amount.to_decimal();
+        amount.rescale(self.scale::<A>());
+        amount
+    }
+
+    pub fn format_balances(&self, balances: &Balances) -> Balances {
+        Balances {
+            available: self.format(balances.available),
+            held: self.format(balances.held),
+            pending: self.format(balances.pending),
+            total: self.format(balances.total),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::Fixed;
+    use crate::{Engine, EngineConfig, EngineError};
+    use pretty_assertions::assert_eq;
+    use rust_decimal_macros::dec;
+
+    fn precision(scale: u32, rounding: Rounding) -> Precision {
+        Precision { scale, rounding }
+    }
+
+    #[test]
+    fn test_round() {
+        let half = dec!(1.005);
+        let cases = [
+            (Rounding::HalfAwayFromZero, Ok(dec!(1.01))),
+            (Rounding::HalfEven, Ok(dec!(1.00))),
+            (Rounding::Truncate, Ok(dec!(1.00))),
+            (Rounding::Reject, Err(Rejection::TooPrecise(2))),
+        ];
+        for (rounding, expected) in cases {
+            assert_eq!(precision(2, rounding).round::<Decimal>(half), expected);
+        }
+        let rounded = precision(2, Rounding::Reject).round::<Decimal>(dec!(1.5000));
+        assert_eq!(rounded.map(|a| a.to_string()), Ok("1.50".to_string()));
+        let padded = precision(8, Rounding::Reject).round::<Decimal>(dec!(0.1));
+        assert_eq!(padded.map(|a| a.to_string()), Ok("0.10000000".to_string()));
+        // Fixed-point amounts hold at most four places.
+        assert!(precision(4, Rounding::Truncate).check::<Fixed>().is_ok());
+        assert!(precision(8, Rounding::Truncate).check::<Decimal>().is_ok());
+        match precision(8, Rounding::Truncate).check::<Fixed>() {
+            Err(EngineError::ScaleTooLarge { scale: 8, max: 4 }) => {}
+            other => panic!("expected ScaleTooLarge, got {:?}", other),
+        }
+        assert_eq!(
+            precision(2, Rounding::Reject)
+                .format(Decimal::ZERO)
+                .to_string(),
+            "0.00"
+        );
+    }
+
+    #[test]
+    fn test_precision() -> Result<(), EngineError> {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,10.005\n\
+                     deposit,2,2,0.123456789\n\
+                     withdrawal,1,3,0.015\n\
+                     deposit,3,3,1\n";
+        let mut outputs = Vec::new();
+        for (scale, rounding) in [
+            (2, Rounding::HalfEven),
+            (2, Rounding::Reject),
+            (8, Rounding::HalfAwayFromZero),
+        ] {
+            let mut engine = Engine::new(EngineConfig {
+                precision: precision(scale, rounding),
+                ..EngineConfig::default()
+            });
+            engine.process_records(input.as_bytes())?;
+            let mut output = Vec::new();
+            engine.write_accounts(&mut output)?;
+            let mut lines: Vec<String> = String::from_utf8(output)
+                .unwrap()
+                .lines()
+                .map(str::to_string)
+                .collect();
+            lines[1..].sort_unstable();
+            outputs.push(lines.join("\n"));
+        }
+        assert_eq!(
+            outputs,
+            vec![
+                "client,available,held,pending,total,locked\n\
+                 1,9.98,0.00,0.00,9.98,false\n\
+                 2,0.12,0.00,0.00,0.12,false\n\
+                 3,0.00,0.00,0.00,0.00,false"
+                    .to_string(),
+                // Every amount has too many places.
+                "client,available,held,pending,total,locked\n\
+                 1,0.00,0.00,0.00,0.00,false\n\
+                 2,0.00,0.00,0.00,0.00,false\n\
+                 3,0.00,0.00,0.00,0.00,false"
+                    .to_string(),
+                "client,available,held,pending,total,locked\n\
+                 1,9.99000000,0.00000000,0.00000000,9.99000000,false\n\
+                 2,0.12345679,0.00000000,0.00000000,0.12345679,false\n\
+                 3,0.00000000,0.00000000,0.00000000,0.00000000,false"
+                    .to_string(),
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,160 @@
+use crate::{ClientId, ColumnIndex, EngineError, Transaction, TransactionType, TxId};
+use csv::{ByteRecord, ReaderBuilder};
+use log::error;
+use rust_decimal::Decimal;
+use std::io;
+use std::str::FromStr;
+
+// Reads transaction rows from CSV input. The record, the scratch buffer and the transaction are reused for every row,
+// so that reading a row does not allocate.
+pub struct TransactionReader<R> {
+    reader: csv::Reader<R>,
+    column_index: ColumnIndex,
+    record: ByteRecord,
+    scratch: String,
+    transaction: Transaction,
+    batch: String,
+}
+
+impl<R: io::Read> TransactionReader<R> {
+    // Reads the header and locates the columns.
+    pub fn new(rdr: R) -> Result<Self, EngineError> {
+        // Whitespaces are removed from the fields when they are parsed, so the reader does not trim them.
+        let mut reader = ReaderBuilder::new().from_reader(rdr);
+        let headers = reader.headers()?;
+        let mut headers_trimmed = Vec::new();
+        // Remove all whitespaces, including whitespaces within a string.
+        for i in headers {
+            let mut i_ = i.to_string();
+            i_.retain(|c| !c.is_whitespace());
+            headers_trimmed.push(i_);
+        }
+
+        let mut column_index = ColumnIndex::new();
+        for (idx, header) in headers_trimmed.iter().enumerate() {
+            match header.as_str() {
+                "type" => {
+                    column_index.check_duplicate_type()?;
+                    column_index.r#type = idx;
+                }
+                "client" => {
+                    column_index.check_duplicate_client()?;
+                    column_index.client = idx;
+                }
+                "tx" => {
+                    column_index.check_duplicate_tx()?;
+                    column_index.tx = idx;
+                }
+                "amount" => {
+                    column_index.check_duplicate_amount()?;
+                    column_index.amount = idx;
+                }
+                "batch" => {
+                    column_index.check_duplicate_batch()?;
+                    column_index.batch = idx;
+                }
+                _ => error!("Unexpected column name: {}", header),
+            }
+        }
+        column_index.check_missing()?; // check if type, client, tx and amount columns do exist in the input csv data
+
+        Ok(Self {
+            reader,
+            column_index,
+            record: ByteRecord::new(),
+            scratch: String::new(),
+            transaction: Transaction::new(TransactionType::Unknown, 0, 0, None),
+            batch: String::new(),
+        })
+    }
+
+    // Reads the next row as a transaction and its batch value, which is empty if the row is not part of a batch.
+    // Both are overwritten by the next call. Returns None at the end of the input.
+    pub fn next_row(&mut self) -> Result<Option<(&Transaction, &str)>, EngineError> {
+        if !self.reader.read_byte_record(&mut self.record)? {
+            return Ok(None);
+        }
+        let (record, scratch, columns) = (&self.record, &mut self.scratch, &self.column_index);
+        let transaction = &mut self.transaction;
+        // The type is decoded from the bytes of the field. Only a field with whitespaces is copied first.
+        let r#type = &record[columns.r#type];
+        transaction.r#type = if r#type.iter().all(u8::is_ascii_graphic) {
+            TransactionType::from_bytes(r#type)
+        } else {
+            TransactionType::from_bytes(strip_whitespace(r#type, scratch)?.as_bytes())
+        };
+        transaction.client =
+            strip_whitespace(&record[columns.client], scratch)?.parse::<ClientId>()?;
+        transaction.tx = strip_whitespace(&record[columns.tx], scratch)?.parse::<TxId>()?;
+        transaction.amount =
+            Decimal::from_str(strip_whitespace(&record[columns.amount], scratch)?).ok();
+        self.batch.clear();
+        if let Some(batch) = record.get(columns.batch) {
+            self.batch.push_str(strip_whitespace(batch, scratch)?);
+        }
+        Ok(Some((&self.transaction, &self.batch)))
+    }
+}
+
+// Returns the field with every whitespace removed, including whitespaces within it.
+// Fields without whitespaces, the common case, are borrowed from the record. Otherwise the other characters are copied into `scratch`.
+fn strip_whitespace<'a>(field: &'a [u8], scratch: &'a mut String) -> Result<&'a str, EngineError> {
+    let field = std::str::from_utf8(field)?;
+    if field.bytes().all(|b| b.is_ascii_graphic()) {
+        return Ok(field);
+    }
+    scratch.clear();
+    scratch.extend(field.chars().filter(|c| !c.is_whitespace()));
+    Ok(scratch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_transaction_reader() -> Result<(), EngineError> {
+        let input = "type, client,tx, amount,batch\n\
+                     deposit,1,1,1.5,\n \
+                     with drawal , 2\u{a0},1 0, 2.0 , b 1\n\
+                     dispute,1,1,,b1\n";
+        let mut reader = TransactionReader::new(input.as_bytes())?;
+        let mut rows = Vec::new();
+        while let Some((transaction, batch)) = reader.next_row()? {
+            rows.push((
+                transaction.r#type,
+                transaction.client,
+                transaction.tx,
+                transaction.amount,
+                batch.to_string(),
+            ));
+        }
+        assert_eq!(
+            rows,
+            vec![
+                (
+                    TransactionType::Deposit,
+                    1,
+                    1,
+                    Some(dec!(1.5)),
+                    String::new()
+                ),
+                (
+                    TransactionType::Withdrawal,
+                    2,
+                    10,
+                    Some(dec!(2.0)),
+                    "b1".to_string()
+                ),
+                (TransactionType::Dispute, 1, 1, None, "b1".to_string()),
+            ]
+        );
+        assert!(matches!(
+            TransactionReader::new("type,client,tx\n".as_bytes()),
+            Err(EngineError::MissingColumnAmount)
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,257 @@
+use crate::{Account, ClientId, EngineError};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+use std::io;
+
+// Largest absolute difference accepted for each amount field. The lock flag always has to match.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct Tolerances {
+    pub available: Decimal,
+    pub held: Decimal,
+    pub pending: Decimal,
+    pub total: Decimal,
+}
+
+impl Tolerances {
+    // Parses `AMOUNT` for every field or `FIELD=AMOUNT` for one field, e.g. `total=0.01`.
+    pub fn set(&mut self, spec: &str) -> Result<(), EngineError> {
+        let (field, amount) = match spec.split_once('=') {
+            Some((field, amount)) => (Some(field.trim()), amount),
+            None => (None, spec),
+        };
+        let amount = amount.trim().parse::<Decimal>()?.abs();
+        match field {
+            None => {
+                *self = Self {
+                    available: amount,
+                    held: amount,
+                    pending: amount,
+                    total: amount,
+                }
+            }
+            Some("available") => self.available = amount,
+            Some("held") => self.held = amount,
+            Some("pending") => self.pending = amount,
+            Some("total") => self.total = amount,
+            Some(field) => return Err(EngineError::UnknownField(field.to_string())),
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Difference {
+    // The client is expected but has no account.
+    MissingClient {
+        client: ClientId,
+    },
+    // The client has an account but is not expected.
+    UnexpectedClient {
+        client: ClientId,
+    },
+    Amount {
+        client: ClientId,
+        field: &'static str,
+        expected: Decimal,
+        actual: Decimal,
+    },
+    Locked {
+        client: ClientId,
+        expected: bool,
+        actual: bool,
+    },
+}
+
+// Row of the reconciliation report.
+#[derive(Serialize)]
+struct DifferenceRow<'a> {
+    client: ClientId,
+    field: &'a str,
+    expected: Option<String>,
+    actual: Option<String>,
+    difference: Option<Decimal>,
+}
+
+impl Difference {
+    fn row(&self) -> DifferenceRow<'_> {
+        match self {
+            Difference::MissingClient { client } => DifferenceRow {
+                client: *client,
+                field: "client",
+                expected: Some("present".to_string()),
+                actual: None,
+                difference: None,
+            },
+            Difference::UnexpectedClient { client } => DifferenceRow {
+                client: *client,
+                field: "client",
+                expected: None,
+                actual: Some("present".to_string()),
+                difference: None,
+            },
+            Difference::Amount {
+                client,
+                field,
+                expected,
+                actual,
+            } => DifferenceRow {
+                client: *client,
+                field,
+                expected: Some(expected.to_string()),
+                actual: Some(actual.to_string()),
+                difference: Some(actual - expected),
+            },
+            Difference::Locked {
+                client,
+                expected,
+                actual,
+            } => DifferenceRow {
+                client: *client,
+                field: "locked",
+                expected: Some(expected.to_string()),
+                actual: Some(actual.to_string()),
+                difference: None,
+            },
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Default, Debug)]
+pub struct Reconciliation {
+    // Ordered by client.
+    pub differences: Vec<Difference>,
+}
+
+impl Reconciliation {
+    // Matches the accounts by client and compares every field.
+    pub fn new(
+        expected: &HashMap<ClientId, Account>,
+        actual: &HashMap<ClientId, Account>,
+        tolerances: &Tolerances,
+    ) -> Self {
+        let clients: BTreeSet<ClientId> = expected.keys().chain(actual.keys()).copied().collect();
+        let mut differences = Vec::new();
+        for client in clients {
+            let (expected, actual) = match (expected.get(&client), actual.get(&client)) {
+                (Some(expected), Some(actual)) => (expected, actual),
+                (Some(_), None) => {
+                    differences.push(Difference::MissingClient { client });
+                    continue;
+                }
+                (None, _) => {
+                    differences.push(Difference::UnexpectedClient { client });
+                    continue;
+                }
+            };
+            for (field, expected, actual, tolerance) in [
+                (
+                    "available",
+                    expected.available,
+                    actual.available,
+                    tolerances.available,
+                ),
+                ("held", expected.held, actual.held, tolerances.held),
+                (
+                    "pending",
+                    expected.pending,
+                    actual.pending,
+                    tolerances.pending,
+                ),
+                ("total", expected.total, actual.total, tolerances.total),
+            ] {
+                if (actual - expected).abs() > tolerance {
+                    differences.push(Difference::Amount {
+                        client,
+                        field,
+                        expected,
+                        actual,
+                    });
+                }
+            }
+            if expected.locked != actual.locked {
+                differences.push(Difference::Locked {
+                    client,
+                    expected: expected.locked,
+                    actual: actual.locked,
+                });
+            }
+        }
+        Self { differences }
+    }
+
+    pub fn is_reconciled(&self) -> bool {
+        self.differences.is_empty()
+    }
+
+    pub fn write_csv<W: io::Write>(&self, wtr: W) -> Result<(), EngineError> {
+        let mut writer = csv::Writer::from_writer(wtr);
+        for difference in &self.differences {
+            writer.serialize(difference.row())?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_csv, process_records};
+    use pretty_assertions::assert_eq;
+    use rust_decimal_macros::dec;
+    use std::fs::File;
+
+    #[test]
+    fn test_reconcile() -> Result<(), EngineError> {
+        let actual = process_records(File::open("test_process_records.csv")?)?;
+        let mut expected = parse_csv(File::open("test_reconcile.csv")?)?;
+        let reconciliation = Reconciliation::new(&expected, &actual, &Tolerances::default());
+        assert_eq!(
+            reconciliation.differences,
+            vec![
+                Difference::Amount {
+                    client: 1,
+                    field: "available",
+                    expected: dec!(-1.4990),
+                    actual: dec!(-1.5000),
+                },
+                Difference::Amount {
+                    client: 1,
+                    field: "total",
+                    expected: dec!(-1.4990),
+                    actual: dec!(-1.5000),
+                },
+                Difference::UnexpectedClient { client: 2 },
+                Difference::Locked {
+                    client: 3,
+                    expected: true,
+                    actual: false,
+                },
+                Difference::MissingClient { client: 4 },
+            ]
+        );
+
+        let mut tolerances = Tolerances::default();
+        tolerances.set("0.001")?;
+        expected.get_mut(&3).unwrap().locked = false;
+        expected.remove(&4);
+        expected.insert(2, actual[&2].clone());
+        let reconciliation = Reconciliation::new(&expected, &actual, &tolerances);
+        assert!(reconciliation.is_reconciled());
+
+        tolerances.set("total=0")?;
+        let reconciliation = Reconciliation::new(&expected, &actual, &tolerances);
+        let mut output = Vec::new();
+        reconciliation.write_csv(&mut output)?;
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,field,expected,actual,difference\n1,total,-1.4990,-1.5000,-0.0010\n"
+        );
+        assert!(matches!(
+            tolerances.set("locked=1"),
+            Err(EngineError::UnknownField(_))
+        ));
+        Ok(())
+    }
+}
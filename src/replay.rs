@@ -0,0 +1,143 @@
+use crate::accounts::Accounts;
+use crate::amount::Amount;
+use crate::precision::Precision;
+use crate::{ClientId, EngineError, Transaction, TxId};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::io;
+
+// Point in the input at which a replay stops.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Cutoff {
+    // Stop right after the first row with this transaction ID.
+    Tx(TxId),
+    // Stop right after the row with this number. Rows are counted from 0 after the header, as in the history and statements.
+    Row(u64),
+}
+
+impl Cutoff {
+    // Whether the replay stops after `transaction`, which was read as row number `row`.
+    pub(crate) fn reached(&self, row: u64, transaction: &Transaction) -> bool {
+        match self {
+            Cutoff::Tx(tx) => transaction.tx == *tx,
+            Cutoff::Row(cutoff) => row >= *cutoff,
+        }
+    }
+}
+
+// Row of the state dump. One row per deposit and per authorization.
+#[derive(Serialize)]
+struct StateRow {
+    client: ClientId,
+    tx: TxId,
+    kind: &'static str,
+    amount: Decimal,
+    disputed: Option<Decimal>,
+    charged_back: Option<Decimal>,
+    refunded: Option<Decimal>,
+    captured: Option<Decimal>,
+    state: String,
+}
+
+// Writes the deposits and authorizations of every account with their state, ordered by client and tx.
+pub fn write_state<A: Amount, W: io::Write>(
+    accounts: &Accounts<A>,
+    precision: &Precision,
+    wtr: W,
+) -> Result<(), EngineError> {
+    let mut writer = csv::Writer::from_writer(wtr);
+    let mut clients: Vec<&ClientId> = accounts.keys().collect();
+    clients.sort_unstable();
+    for client in clients {
+        let account = &accounts[client];
+        let mut rows = Vec::new();
+        for (tx, deposit) in &account.deposited {
+            rows.push(StateRow {
+                client: *client,
+                tx: *tx,
+                kind: "deposit",
+                amount: precision.format(deposit.amount),
+                disputed: Some(precision.format(deposit.disputed)),
+                charged_back: Some(precision.format(deposit.charged_back)),
+                refunded: Some(precision.format(deposit.refunded)),
+                captured: None,
+                state: format!("{:?}", deposit.state),
+            });
+        }
+        for (tx, authorization) in &account.authorized {
+            rows.push(StateRow {
+                client: *client,
+                tx: *tx,
+                kind: "authorization",
+                amount: precision.format(authorization.amount),
+                disputed: None,
+                charged_back: None,
+                refunded: None,
+                captured: Some(precision.format(authorization.captured)),
+                state: format!("{:?}", authorization.state),
+            });
+        }
+        rows.sort_unstable_by_key(|row| row.tx);
+        for row in rows {
+            writer.serialize(row)?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DepositState, Engine, EngineConfig};
+    use pretty_assertions::assert_eq;
+    use rust_decimal_macros::dec;
+    use std::fs::File;
+
+    #[test]
+    fn test_replay() -> Result<(), EngineError> {
+        // Row 52 resolves client 3's dispute on tx 8.
+        let mut engine = Engine::new(EngineConfig::default());
+        assert!(engine.replay(File::open("test_process_records.csv")?, Cutoff::Row(52))?);
+        let account = &engine.accounts()[&3];
+        assert_eq!(account.available(), dec!(1000.0000));
+        assert_eq!(account.held(), dec!(0));
+        assert_eq!(
+            account.deposited(8).unwrap().state(),
+            DepositState::NotDisputed
+        );
+        let mut output = Vec::new();
+        write_state(engine.accounts(), &engine.output_precision(), &mut output)?;
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .contains("\n3,8,deposit,1000.0000,0.0000,0.0000,0.0000,,NotDisputed\n"));
+
+        // Tx 8 first appears in row 33, the deposit of client 3.
+        let mut engine = Engine::new(EngineConfig::default());
+        assert!(engine.replay(File::open("test_process_records.csv")?, Cutoff::Tx(8))?);
+        let account = &engine.accounts()[&3];
+        assert_eq!(account.available(), dec!(1000.0000));
+        assert_eq!(engine.accounts().len(), 3);
+
+        // A cutoff inside a batch stops at the end of the batch. Row 1 is the deposit of batch a, row 6 the first row of batch c.
+        let mut engine = Engine::new(EngineConfig::default());
+        assert!(engine.replay(File::open("test_batches.csv")?, Cutoff::Row(1))?);
+        assert_eq!(engine.accounts()[&1].available(), dec!(6.0000));
+        assert_eq!(engine.accounts()[&2].available(), dec!(5.0000));
+        let mut engine = Engine::new(EngineConfig::default());
+        assert!(engine.replay(File::open("test_batches.csv")?, Cutoff::Row(6))?);
+        assert_eq!(engine.accounts()[&3].available(), dec!(3.0000));
+        assert_eq!(engine.accounts()[&1].held(), dec!(10.0000));
+
+        // Batch d is rolled back by its withdrawal, and the replay stops before the row that reuses tx 8.
+        let mut engine = Engine::new(EngineConfig::default());
+        assert!(engine.replay(File::open("test_batches.csv")?, Cutoff::Tx(8))?);
+        assert_eq!(engine.accounts()[&1].available(), dec!(-4.0000));
+        assert!(engine.accounts()[&1].deposited(8).is_none());
+
+        // The replay reports a cutoff that is never reached.
+        let mut engine = Engine::new(EngineConfig::default());
+        assert!(!engine.replay(File::open("test_process_records.csv")?, Cutoff::Tx(999))?);
+        Ok(())
+    }
+}
@@ -0,0 +1,148 @@
+use crate::TxId;
+#[cfg(not(feature = "wide-ids"))]
+use roaring::RoaringBitmap as Roaring;
+#[cfg(feature = "wide-ids")]
+use roaring::RoaringTreemap as Roaring;
+use std::collections::HashSet;
+use std::fmt;
+use std::mem;
+
+// Set of the transaction IDs seen so far, used to reject rows reusing an ID.
+pub trait SeenIds: fmt::Debug {
+    // Returns false if the ID has been seen before.
+    fn insert(&mut self, tx: TxId) -> bool;
+    fn contains(&self, tx: TxId) -> bool;
+    // Forgets an ID, e.g. when the batch it first appeared in is rolled back.
+    fn remove(&mut self, tx: TxId) -> bool;
+    fn len(&self) -> u64;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    // Approximate number of heap bytes used.
+    fn heap_size(&self) -> usize;
+    fn box_clone(&self) -> Box<dyn SeenIds>;
+}
+
+impl Clone for Box<dyn SeenIds> {
+    fn clone(&self) -> Self {
+        self.box_clone()
+    }
+}
+
+impl SeenIds for HashSet<TxId> {
+    fn insert(&mut self, tx: TxId) -> bool {
+        HashSet::insert(self, tx)
+    }
+
+    fn contains(&self, tx: TxId) -> bool {
+        HashSet::contains(self, &tx)
+    }
+
+    fn remove(&mut self, tx: TxId) -> bool {
+        HashSet::remove(self, &tx)
+    }
+
+    fn len(&self) -> u64 {
+        HashSet::len(self) as u64
+    }
+
+    // Every bucket stores the ID and one control byte.
+    fn heap_size(&self) -> usize {
+        self.capacity() * (mem::size_of::<TxId>() + 1)
+    }
+
+    fn box_clone(&self) -> Box<dyn SeenIds> {
+        Box::new(self.clone())
+    }
+}
+
+// Stores IDs in chunks of 2^16 as sorted arrays, bitmaps or runs, whichever is smallest.
+// Dense or sequential IDs take a fraction of a bit each, sparse IDs about two bytes each.
+// 64-bit IDs are split by their upper 32 bits into one bitmap each.
+impl SeenIds for Roaring {
+    fn insert(&mut self, tx: TxId) -> bool {
+        Roaring::insert(self, tx)
+    }
+
+    fn contains(&self, tx: TxId) -> bool {
+        Roaring::contains(self, tx)
+    }
+
+    fn remove(&mut self, tx: TxId) -> bool {
+        Roaring::remove(self, tx)
+    }
+
+    fn len(&self) -> u64 {
+        Roaring::len(self)
+    }
+
+    fn heap_size(&self) -> usize {
+        self.serialized_size()
+    }
+
+    fn box_clone(&self) -> Box<dyn SeenIds> {
+        Box::new(self.clone())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum SeenIdsKind {
+    #[default]
+    HashSet,
+    Roaring,
+}
+
+impl SeenIdsKind {
+    pub fn build(self) -> Box<dyn SeenIds> {
+        match self {
+            SeenIdsKind::HashSet => Box::new(HashSet::new()),
+            SeenIdsKind::Roaring => Box::new(Roaring::new()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate::{Generator, GeneratorConfig};
+    use crate::history::HistoryFilter;
+    use crate::{Engine, EngineConfig, EngineError};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_seen_ids() -> Result<(), EngineError> {
+        let mut generated = Vec::new();
+        Generator::new(GeneratorConfig {
+            seed: 3,
+            duplicate_rate: 0.1,
+            ..GeneratorConfig::default()
+        })
+        .write_csv(&mut generated)?;
+        for input in [std::fs::read("test_process_records.csv")?, generated] {
+            let mut outcomes = Vec::new();
+            for seen_ids in [SeenIdsKind::HashSet, SeenIdsKind::Roaring] {
+                let mut engine = Engine::new(EngineConfig {
+                    seen_ids,
+                    history: true,
+                    ..EngineConfig::default()
+                });
+                engine.process_records(input.as_slice())?;
+                let results: Vec<_> = engine
+                    .query(&HistoryFilter::default())
+                    .into_iter()
+                    .map(|record| record.result.clone())
+                    .collect();
+                outcomes.push((engine.accounts().clone(), results));
+            }
+            assert_eq!(outcomes[0], outcomes[1]);
+        }
+
+        let mut seen = SeenIdsKind::Roaring.build();
+        assert!(seen.insert(TxId::MAX));
+        assert!(!seen.insert(TxId::MAX));
+        assert!(seen.contains(TxId::MAX));
+        assert!(seen.remove(TxId::MAX));
+        assert!(seen.is_empty());
+        Ok(())
+    }
+}
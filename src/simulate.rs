@@ -0,0 +1,157 @@
+use crate::accounts::Accounts;
+use crate::amount::Amount;
+use crate::precision::Precision;
+use crate::{Account, Balances, ClientId, EngineError};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io;
+
+// How a simulation changed an account.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct AccountChange {
+    pub client: ClientId,
+    // None if the simulation created the account.
+    pub before: Option<Balances>,
+    pub after: Balances,
+    pub locked_before: bool,
+    pub locked_after: bool,
+}
+
+impl AccountChange {
+    pub fn newly_locked(&self) -> bool {
+        !self.locked_before && self.locked_after
+    }
+}
+
+// Row of the simulation report.
+#[derive(Serialize)]
+struct ChangeRow {
+    client: ClientId,
+    new_account: bool,
+    available_before: Option<Decimal>,
+    available_after: Decimal,
+    held_before: Option<Decimal>,
+    held_after: Decimal,
+    pending_before: Option<Decimal>,
+    pending_after: Decimal,
+    total_before: Option<Decimal>,
+    total_after: Decimal,
+    locked_before: bool,
+    locked_after: bool,
+}
+
+#[derive(Clone, PartialEq, Eq, Default, Debug)]
+pub struct Simulation {
+    // Accounts whose balances or lock changed, ordered by client.
+    pub changes: Vec<AccountChange>,
+    // Amounts are written with the scale of the engine.
+    pub precision: Precision,
+}
+
+impl Simulation {
+    // Compares the accounts saved before the simulation with the accounts after it.
+    pub(crate) fn new<A: Amount>(
+        saved: &HashMap<ClientId, Option<Account<A>>>,
+        accounts: &Accounts<A>,
+        precision: Precision,
+    ) -> Self {
+        let mut clients: Vec<&ClientId> = saved.keys().collect();
+        clients.sort_unstable();
+        let mut changes = Vec::new();
+        for client in clients {
+            let after = match accounts.get(client) {
+                Some(account) => account,
+                None => continue,
+            };
+            let before = saved[client].as_ref();
+            let change = AccountChange {
+                client: *client,
+                before: before.map(Balances::from),
+                after: Balances::from(after),
+                locked_before: before.is_some_and(|account| account.locked),
+                locked_after: after.locked,
+            };
+            let unchanged = match change.before {
+                Some(balances) => balances == change.after,
+                // Rejected rows create empty accounts.
+                None => change.after == Balances::from(&Account::<A>::new(*client)),
+            };
+            if !unchanged || change.locked_before != change.locked_after {
+                changes.push(change);
+            }
+        }
+        Self { changes, precision }
+    }
+
+    pub fn newly_locked(&self) -> impl Iterator<Item = &AccountChange> {
+        self.changes.iter().filter(|change| change.newly_locked())
+    }
+
+    pub fn write_csv<W: io::Write>(&self, wtr: W) -> Result<(), EngineError> {
+        let precision = &self.precision;
+        let mut writer = csv::Writer::from_writer(wtr);
+        for change in &self.changes {
+            let before = change.before.map(|b| precision.format_balances(&b));
+            let after = precision.format_balances(&change.after);
+            writer.serialize(ChangeRow {
+                client: change.client,
+                new_account: before.is_none(),
+                available_before: before.map(|b| b.available),
+                available_after: after.available,
+                held_before: before.map(|b| b.held),
+                held_after: after.held,
+                pending_before: before.map(|b| b.pending),
+                pending_after: after.pending,
+                total_before: before.map(|b| b.total),
+                total_after: after.total,
+                locked_before: change.locked_before,
+                locked_after: change.locked_after,
+            })?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{process_records, Engine, EngineConfig};
+    use pretty_assertions::assert_eq;
+    use std::fs::File;
+
+    #[test]
+    fn test_simulate() -> Result<(), EngineError> {
+        let test_file_path = "test_process_records.csv";
+        let mut engine = Engine::new(EngineConfig {
+            ledger: true,
+            ..EngineConfig::default()
+        });
+        engine.process_records(File::open(test_file_path)?)?;
+        let simulation = engine.simulate(File::open("test_simulate.csv")?)?;
+        // Client 3's disputed deposit is charged back. Client 1 is locked, so its deposit is rejected. Client 4 is new.
+        let mut output = Vec::new();
+        simulation.write_csv(&mut output)?;
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,new_account,available_before,available_after,held_before,held_after,pending_before,pending_after,total_before,total_after,locked_before,locked_after\n\
+             3,false,0.0000,0.0000,1000.0000,0.0000,0.0000,0.0000,1000.0000,0.0000,false,true\n\
+             4,true,,2.0000,,0.0000,,0.0000,,2.0000,false,false\n"
+        );
+        assert_eq!(
+            simulation
+                .newly_locked()
+                .map(|change| change.client)
+                .collect::<Vec<_>>(),
+            vec![3]
+        );
+        // The engine state is not touched.
+        assert_eq!(
+            engine.accounts(),
+            &process_records(File::open(test_file_path)?)?
+        );
+        engine.verify_ledger()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,268 @@
+use crate::history::HistoryRecord;
+use crate::precision::Precision;
+use crate::{Balances, ClientId, DepositState, EngineError, TransactionType, TxId};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::io;
+use std::ops::Range;
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum DisputeChange {
+    Opened,
+    Closed,
+}
+
+impl DisputeChange {
+    fn of(record: &HistoryRecord) -> Option<Self> {
+        match record.r#type {
+            TransactionType::Dispute | TransactionType::PreArbitration => {
+                Some(DisputeChange::Opened)
+            }
+            // A partial resolve leaves the rest of the dispute open.
+            TransactionType::Resolve
+            | TransactionType::Chargeback
+            | TransactionType::ArbitrationWon
+            | TransactionType::ArbitrationLost
+                if record.deposit_state != Some(DepositState::Disputed) =>
+            {
+                Some(DisputeChange::Closed)
+            }
+            _ => None,
+        }
+    }
+}
+
+// A transaction applied to the client's account together with the running balances after it.
+#[derive(Clone, PartialEq, Eq, Serialize, Debug)]
+pub struct StatementLine {
+    pub row: u64,
+    pub tx: TxId,
+    pub r#type: TransactionType,
+    pub amount: Option<Decimal>,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub pending: Decimal,
+    pub total: Decimal,
+    pub dispute: Option<DisputeChange>,
+}
+
+// Row of the statement CSV. The opening and closing balances are written as rows without a tx.
+#[derive(Serialize)]
+struct StatementRow<'a> {
+    row: Option<u64>,
+    tx: Option<TxId>,
+    r#type: &'a str,
+    amount: Option<Decimal>,
+    available: Decimal,
+    held: Decimal,
+    pending: Decimal,
+    total: Decimal,
+    dispute: Option<DisputeChange>,
+}
+
+impl<'a> StatementRow<'a> {
+    fn balance(r#type: &'a str, balances: Balances) -> Self {
+        Self {
+            row: None,
+            tx: None,
+            r#type,
+            amount: None,
+            available: balances.available,
+            held: balances.held,
+            pending: balances.pending,
+            total: balances.total,
+            dispute: None,
+        }
+    }
+}
+
+// Statement of one client for a period. Input files carry no timestamps, so a period is a range of rows.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Statement {
+    pub client: ClientId,
+    pub rows: Range<u64>,
+    pub opening: Balances,
+    pub lines: Vec<StatementLine>,
+    pub disputes_opened: usize,
+    pub disputes_closed: usize,
+    pub closing: Balances,
+    // Amounts are written with the scale of the engine.
+    pub precision: Precision,
+}
+
+impl Statement {
+    // Builds the statement from the client's history in the order the records were applied.
+    pub fn new<'a, I>(client: ClientId, rows: Range<u64>, history: I, precision: Precision) -> Self
+    where
+        I: IntoIterator<Item = &'a HistoryRecord>,
+    {
+        let zero = Balances {
+            available: Decimal::ZERO,
+            held: Decimal::ZERO,
+            pending: Decimal::ZERO,
+            total: Decimal::ZERO,
+        };
+        let mut statement = Self {
+            client,
+            rows: rows.clone(),
+            opening: zero,
+            lines: Vec::new(),
+            disputes_opened: 0,
+            disputes_closed: 0,
+            closing: zero,
+            precision,
+        };
+        for record in history {
+            if record.row < rows.start {
+                statement.opening = record.balances;
+                statement.closing = record.balances;
+                continue;
+            }
+            if record.row >= rows.end {
+                break;
+            }
+            // Rejected rows did not change the account.
+            if !record.applied() {
+                continue;
+            }
+            let dispute = DisputeChange::of(record);
+            match dispute {
+                Some(DisputeChange::Opened) => statement.disputes_opened += 1,
+                Some(DisputeChange::Closed) => statement.disputes_closed += 1,
+                None => {}
+            }
+            statement.lines.push(StatementLine {
+                row: record.row,
+                tx: record.tx,
+                r#type: record.r#type,
+                amount: record.amount,
+                available: record.balances.available,
+                held: record.balances.held,
+                pending: record.balances.pending,
+                total: record.balances.total,
+                dispute,
+            });
+            statement.closing = record.balances;
+        }
+        statement
+    }
+
+    pub fn write_csv<W: io::Write>(&self, wtr: W) -> Result<(), EngineError> {
+        let precision = &self.precision;
+        let mut writer = csv::Writer::from_writer(wtr);
+        writer.serialize(StatementRow::balance(
+            "opening",
+            precision.format_balances(&self.opening),
+        ))?;
+        for line in &self.lines {
+            writer.serialize(StatementRow {
+                row: Some(line.row),
+                tx: Some(line.tx),
+                r#type: line.r#type.as_str(),
+                amount: line.amount.map(|amount| precision.format(amount)),
+                available: precision.format(line.available),
+                held: precision.format(line.held),
+                pending: precision.format(line.pending),
+                total: precision.format(line.total),
+                dispute: line.dispute,
+            })?;
+        }
+        writer.serialize(StatementRow::balance(
+            "closing",
+            precision.format_balances(&self.closing),
+        ))?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn write_text<W: io::Write>(&self, mut wtr: W) -> Result<(), EngineError> {
+        let end = if self.rows.end == u64::MAX {
+            "end of input".to_string()
+        } else {
+            format!("row {}", self.rows.end)
+        };
+        writeln!(
+            wtr,
+            "Statement for client {}, from row {} to {}",
+            self.client, self.rows.start, end
+        )?;
+        let precision = &self.precision;
+        writeln!(
+            wtr,
+            "Opening balance: {}",
+            precision.format_balances(&self.opening)
+        )?;
+        writeln!(
+            wtr,
+            "{:>8} {:>10} {:<16} {:>20} {:>20} {:>20} {:>20} {:>20}  dispute",
+            "row", "tx", "type", "amount", "available", "held", "pending", "total"
+        )?;
+        for line in &self.lines {
+            writeln!(
+                wtr,
+                "{:>8} {:>10} {:<16} {:>20} {:>20} {:>20} {:>20} {:>20}  {}",
+                line.row,
+                line.tx,
+                line.r#type,
+                line.amount
+                    .map(|amount| precision.format(amount).to_string())
+                    .unwrap_or_default(),
+                precision.format(line.available),
+                precision.format(line.held),
+                precision.format(line.pending),
+                precision.format(line.total),
+                match line.dispute {
+                    Some(DisputeChange::Opened) => "opened",
+                    Some(DisputeChange::Closed) => "closed",
+                    None => "",
+                }
+            )?;
+        }
+        writeln!(
+            wtr,
+            "Disputes opened: {}, closed: {}",
+            self.disputes_opened, self.disputes_closed
+        )?;
+        writeln!(
+            wtr,
+            "Closing balance: {}",
+            precision.format_balances(&self.closing)
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Engine, EngineConfig};
+    use pretty_assertions::assert_eq;
+    use std::fs::File;
+
+    #[test]
+    fn test_statement() -> Result<(), EngineError> {
+        let test_file_path = "test_process_records.csv";
+        let test_rdr = File::open(test_file_path)?;
+        let mut engine = Engine::new(EngineConfig {
+            history: true,
+            ..EngineConfig::default()
+        });
+        engine.process_records(test_rdr)?;
+        let statement = engine.statement(3, 40..u64::MAX);
+        assert_eq!(statement.disputes_opened, 2);
+        assert_eq!(statement.disputes_closed, 1);
+        let mut output = Vec::new();
+        statement.write_csv(&mut output)?;
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "row,tx,type,amount,available,held,pending,total,dispute\n\
+             ,,opening,,1000.0000,0.0000,0.0000,1000.0000,\n\
+             51,8,dispute,,0.0000,1000.0000,0.0000,1000.0000,opened\n\
+             52,8,resolve,,1000.0000,0.0000,0.0000,1000.0000,closed\n\
+             54,8,dispute,,0.0000,1000.0000,0.0000,1000.0000,opened\n\
+             ,,closing,,0.0000,1000.0000,0.0000,1000.0000,\n"
+        );
+        Ok(())
+    }
+}
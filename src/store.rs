@@ -0,0 +1,856 @@
+use crate::amount::Amount;
+use crate::{
+    Account, Authorization, AuthorizationState, ClientId, Deposit, DepositState, DepositTotals,
+    EngineError, TxId,
+};
+use log::error;
+use rusqlite::{params, Connection, OptionalExtension};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+// Where the deposits of the accounts are kept between the rows that reference them.
+// The engine loads the deposits a row needs into `Account.deposited` before applying it and evicts them afterwards, so that only the deposits in use are held in memory.
+pub trait DepositStore<A: Amount = Decimal>: fmt::Debug {
+    // Writes the deposits held in memory by the accounts to the store and drops them from memory.
+    // `tx_ids` are the transaction IDs first seen since the last call, for stores that keep them.
+    fn evict(
+        &mut self,
+        accounts: &mut [&mut Account<A>],
+        tx_ids: &[TxId],
+    ) -> Result<(), EngineError>;
+    // Reads a deposit of the client. None if the store does not have it.
+    fn deposit(&self, client: ClientId, tx: TxId) -> Result<Option<Deposit<A>>, EngineError>;
+    // Reads every deposit of the client.
+    fn deposits(&self, client: ClientId) -> Result<Vec<(TxId, Deposit<A>)>, EngineError>;
+    // Makes everything evicted so far durable, for stores that delay it. Called once the input is processed.
+    fn flush(&mut self) -> Result<(), EngineError> {
+        Ok(())
+    }
+}
+
+// Keeps every deposit in its account for the lifetime of the engine.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct InMemoryDeposits;
+
+impl<A: Amount> DepositStore<A> for InMemoryDeposits {
+    fn evict(
+        &mut self,
+        _accounts: &mut [&mut Account<A>],
+        _tx_ids: &[TxId],
+    ) -> Result<(), EngineError> {
+        Ok(())
+    }
+
+    fn deposit(&self, _client: ClientId, _tx: TxId) -> Result<Option<Deposit<A>>, EngineError> {
+        Ok(None)
+    }
+
+    fn deposits(&self, _client: ClientId) -> Result<Vec<(TxId, Deposit<A>)>, EngineError> {
+        Ok(Vec::new())
+    }
+}
+
+// Keeps the deposits in an SQLite file. The file is scratch space for one run: it is created by the store, written without
+// journaling and deleted when the store is dropped. An existing file is never opened, so that it cannot be overwritten.
+pub struct SqliteDeposits {
+    connection: Connection,
+    // File created by the store. None for a temporary store, which SQLite deletes itself.
+    path: Option<PathBuf>,
+}
+
+impl fmt::Debug for SqliteDeposits {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SqliteDeposits")
+            .field("path", &self.connection.path())
+            .finish()
+    }
+}
+
+impl SqliteDeposits {
+    // Creates the file at `path`. Fails if it already exists, e.g. if it is a database of `SqliteDatabase`.
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, EngineError> {
+        let path = path.as_ref();
+        match OpenOptions::new().write(true).create_new(true).open(path) {
+            Ok(_) => {}
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists => {
+                return Err(EngineError::StoreExists(path.display().to_string()))
+            }
+            Err(error) => return Err(error.into()),
+        }
+        let store = Self {
+            connection: Connection::open(path)?,
+            path: Some(path.to_path_buf()),
+        };
+        store.init()?;
+        Ok(store)
+    }
+
+    // Uses a temporary file that is deleted when the store is dropped.
+    pub fn temporary() -> Result<Self, EngineError> {
+        let store = Self {
+            connection: Connection::open("")?,
+            path: None,
+        };
+        store.init()?;
+        Ok(store)
+    }
+
+    fn init(&self) -> Result<(), EngineError> {
+        self.connection.execute_batch(&format!(
+            "PRAGMA journal_mode = OFF;
+             PRAGMA synchronous = OFF;
+             {}",
+            DEPOSITS_TABLE
+        ))?;
+        Ok(())
+    }
+}
+
+// Deletes the file created by the store once its connection is closed.
+impl Drop for SqliteDeposits {
+    fn drop(&mut self) {
+        let path = match self.path.take() {
+            Some(path) => path,
+            None => return,
+        };
+        if let Ok(closed) = Connection::open_in_memory() {
+            drop(std::mem::replace(&mut self.connection, closed));
+        }
+        if let Err(error) = std::fs::remove_file(&path) {
+            error!(
+                "The deposit store {} could not be deleted: {}.",
+                path.display(),
+                error
+            );
+        }
+    }
+}
+
+fn state_name(state: DepositState) -> &'static str {
+    match state {
+        DepositState::Pending => "pending",
+        DepositState::NotDisputed => "not_disputed",
+        DepositState::Disputed => "disputed",
+        DepositState::Chargebacked => "chargebacked",
+        DepositState::Represented => "represented",
+        DepositState::PreArbitration => "prearbitration",
+        DepositState::ArbitrationWon => "arbitration_won",
+        DepositState::ArbitrationLost => "arbitration_lost",
+    }
+}
+
+fn parse_state(name: &str) -> Option<DepositState> {
+    Some(match name {
+        "pending" => DepositState::Pending,
+        "not_disputed" => DepositState::NotDisputed,
+        "disputed" => DepositState::Disputed,
+        "chargebacked" => DepositState::Chargebacked,
+        "represented" => DepositState::Represented,
+        "prearbitration" => DepositState::PreArbitration,
+        "arbitration_won" => DepositState::ArbitrationWon,
+        "arbitration_lost" => DepositState::ArbitrationLost,
+        _ => return None,
+    })
+}
+
+fn authorization_state_name(state: AuthorizationState) -> &'static str {
+    match state {
+        AuthorizationState::Authorized => "authorized",
+        AuthorizationState::Captured => "captured",
+        AuthorizationState::Voided => "voided",
+        AuthorizationState::Expired => "expired",
+    }
+}
+
+fn parse_authorization_state(name: &str) -> Option<AuthorizationState> {
+    Some(match name {
+        "authorized" => AuthorizationState::Authorized,
+        "captured" => AuthorizationState::Captured,
+        "voided" => AuthorizationState::Voided,
+        "expired" => AuthorizationState::Expired,
+        _ => return None,
+    })
+}
+
+fn unknown_state(index: usize, state: &str) -> rusqlite::Error {
+    rusqlite::Error::FromSqlConversionFailure(
+        index,
+        rusqlite::types::Type::Text,
+        format!("unknown state `{}`", state).into(),
+    )
+}
+
+// SQLite integers are `i64`. IDs are stored with the bits of the `u64`, so that IDs above `i64::MAX` under the `wide-ids`
+// feature are stored as negative numbers and read back unchanged.
+// Such IDs sort before every smaller ID in SQLite, so ORDER BY and range queries on ID columns do not follow the order of the IDs.
+// The engine only looks IDs up by equality. IDs are ordered after `read_id` has brought them back to `u64`.
+fn sql_id<T: Into<u64>>(id: T) -> i64 {
+    id.into() as i64
+}
+
+fn read_id<T: TryFrom<u64>>(row: &rusqlite::Row, index: usize) -> rusqlite::Result<T> {
+    let id: i64 = row.get(index)?;
+    T::try_from(id as u64).map_err(|_| rusqlite::Error::IntegralValueOutOfRange(index, id))
+}
+
+fn read_amount<A: Amount>(row: &rusqlite::Row, index: usize) -> rusqlite::Result<A> {
+    let text: String = row.get(index)?;
+    let conversion_failure = |error: Box<dyn std::error::Error + Send + Sync>| {
+        rusqlite::Error::FromSqlConversionFailure(index, rusqlite::types::Type::Text, error)
+    };
+    let amount = Decimal::from_str(&text).map_err(|error| conversion_failure(Box::new(error)))?;
+    A::from_decimal(amount)
+        .ok_or_else(|| conversion_failure(format!("amount {} is out of range", amount).into()))
+}
+
+// Reads a deposit from the columns amount, disputed, charged_back, refunded and state, starting at `first`.
+fn read_deposit<A: Amount>(row: &rusqlite::Row, first: usize) -> rusqlite::Result<Deposit<A>> {
+    let state: String = row.get(first + 4)?;
+    let state = parse_state(&state).ok_or_else(|| unknown_state(first + 4, &state))?;
+    Ok(Deposit {
+        amount: read_amount(row, first)?,
+        disputed: read_amount(row, first + 1)?,
+        charged_back: read_amount(row, first + 2)?,
+        refunded: read_amount(row, first + 3)?,
+        state,
+    })
+}
+
+impl<A: Amount> DepositStore<A> for SqliteDeposits {
+    fn evict(
+        &mut self,
+        accounts: &mut [&mut Account<A>],
+        _tx_ids: &[TxId],
+    ) -> Result<(), EngineError> {
+        let transaction = self.connection.transaction()?;
+        for account in accounts.iter() {
+            write_deposits(&transaction, account)?;
+        }
+        transaction.commit()?;
+        // Deposits are only dropped once they are safely written.
+        for account in accounts.iter_mut() {
+            account.deposited.clear();
+        }
+        Ok(())
+    }
+
+    fn deposit(&self, client: ClientId, tx: TxId) -> Result<Option<Deposit<A>>, EngineError> {
+        read_stored_deposit(&self.connection, client, tx)
+    }
+
+    fn deposits(&self, client: ClientId) -> Result<Vec<(TxId, Deposit<A>)>, EngineError> {
+        read_stored_deposits(&self.connection, client)
+    }
+}
+
+// Amounts are stored as text so that they keep their exact value and scale.
+const DEPOSITS_TABLE: &str = "CREATE TABLE IF NOT EXISTS deposits (
+    client INTEGER NOT NULL,
+    tx INTEGER NOT NULL,
+    amount TEXT NOT NULL,
+    disputed TEXT NOT NULL,
+    charged_back TEXT NOT NULL,
+    refunded TEXT NOT NULL,
+    state TEXT NOT NULL,
+    PRIMARY KEY (client, tx)
+) WITHOUT ROWID;";
+
+fn write_deposits<A: Amount>(
+    connection: &Connection,
+    account: &Account<A>,
+) -> Result<(), EngineError> {
+    let mut statement = connection.prepare_cached(
+        "INSERT OR REPLACE INTO deposits (client, tx, amount, disputed, charged_back, refunded, state)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+    )?;
+    for (tx, deposit) in &account.deposited {
+        statement.execute(params![
+            sql_id(account.client),
+            sql_id(*tx),
+            deposit.amount.to_string(),
+            deposit.disputed.to_string(),
+            deposit.charged_back.to_string(),
+            deposit.refunded.to_string(),
+            state_name(deposit.state),
+        ])?;
+    }
+    Ok(())
+}
+
+fn read_stored_deposit<A: Amount>(
+    connection: &Connection,
+    client: ClientId,
+    tx: TxId,
+) -> Result<Option<Deposit<A>>, EngineError> {
+    let mut statement = connection.prepare_cached(
+        "SELECT amount, disputed, charged_back, refunded, state FROM deposits WHERE client = ?1 AND tx = ?2",
+    )?;
+    Ok(statement
+        .query_row(params![sql_id(client), sql_id(tx)], |row| {
+            read_deposit(row, 0)
+        })
+        .optional()?)
+}
+
+fn read_stored_deposits<A: Amount>(
+    connection: &Connection,
+    client: ClientId,
+) -> Result<Vec<(TxId, Deposit<A>)>, EngineError> {
+    let mut statement = connection.prepare_cached(
+        "SELECT tx, amount, disputed, charged_back, refunded, state FROM deposits WHERE client = ?1",
+    )?;
+    let rows = statement.query_map(params![sql_id(client)], |row| {
+        Ok((read_id(row, 0)?, read_deposit(row, 1)?))
+    })?;
+    Ok(rows.collect::<Result<Vec<_>, _>>()?)
+}
+
+// Accounts and transaction IDs saved in a database.
+pub type Saved<A> = (HashMap<ClientId, Account<A>>, Vec<TxId>);
+
+// Number of rows written in one SQLite transaction by default. Every row or batch is committed as soon as it is applied.
+pub const COMMIT_ROWS: usize = 1;
+
+// Keeps the accounts, their deposits and authorizations, and the transaction IDs seen so far in an SQLite database between runs.
+// By default every row, and every batch as one unit, is committed once it is applied, so a crash loses nothing that was applied.
+// With `commit_rows` above 1, rows are written in SQLite transactions of that many rows, committed when full and when the input
+// is processed: a long input then syncs the file less often, but a crash loses the uncommitted rows.
+// The database never holds a partly applied row or batch.
+// The clock, the pending expiries and settlements, the ledger and the history are not kept.
+pub struct SqliteDatabase {
+    connection: Connection,
+    commit_rows: usize,
+    // Rows written in the open SQLite transaction, if any.
+    uncommitted: usize,
+}
+
+impl fmt::Debug for SqliteDatabase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SqliteDatabase")
+            .field("path", &self.connection.path())
+            .finish()
+    }
+}
+
+impl SqliteDatabase {
+    // Opens the database, creating it if it does not exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, EngineError> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(&format!(
+            "PRAGMA journal_mode = WAL;
+             PRAGMA synchronous = NORMAL;
+             CREATE TABLE IF NOT EXISTS accounts (
+                 client INTEGER PRIMARY KEY,
+                 available TEXT NOT NULL,
+                 held TEXT NOT NULL,
+                 pending TEXT NOT NULL,
+                 total TEXT NOT NULL,
+                 locked INTEGER NOT NULL,
+                 disputed_total TEXT NOT NULL,
+                 pending_total TEXT NOT NULL,
+                 chargebacked_count INTEGER NOT NULL
+             );
+             CREATE TABLE IF NOT EXISTS authorizations (
+                 client INTEGER NOT NULL,
+                 tx INTEGER NOT NULL,
+                 amount TEXT NOT NULL,
+                 captured TEXT NOT NULL,
+                 state TEXT NOT NULL,
+                 PRIMARY KEY (client, tx)
+             ) WITHOUT ROWID;
+             CREATE TABLE IF NOT EXISTS seen_ids (
+                 tx INTEGER PRIMARY KEY
+             );
+             {}",
+            DEPOSITS_TABLE
+        ))?;
+        Ok(Self {
+            connection,
+            commit_rows: COMMIT_ROWS,
+            uncommitted: 0,
+        })
+    }
+
+    // Sets the number of rows written in one SQLite transaction. 1 commits every row on its own.
+    pub fn commit_rows(mut self, rows: usize) -> Self {
+        self.commit_rows = rows.max(1);
+        self
+    }
+
+    // Commits the rows written so far.
+    pub fn commit(&mut self) -> Result<(), EngineError> {
+        if self.uncommitted > 0 {
+            self.connection.execute_batch("COMMIT")?;
+            self.uncommitted = 0;
+        }
+        Ok(())
+    }
+
+    fn write<A: Amount>(
+        &self,
+        accounts: &[&mut Account<A>],
+        tx_ids: &[TxId],
+    ) -> Result<(), EngineError> {
+        let connection = &self.connection;
+        let mut account_statement = connection.prepare_cached(
+            "INSERT OR REPLACE INTO accounts (client, available, held, pending, total, locked, disputed_total, pending_total, chargebacked_count)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        )?;
+        let mut authorization_statement = connection.prepare_cached(
+            "INSERT OR REPLACE INTO authorizations (client, tx, amount, captured, state)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+        )?;
+        for account in accounts.iter() {
+            account_statement.execute(params![
+                sql_id(account.client),
+                account.available.to_string(),
+                account.held.to_string(),
+                account.pending.to_string(),
+                account.total.to_string(),
+                account.locked,
+                account.deposit_totals.disputed.to_string(),
+                account.deposit_totals.pending.to_string(),
+                account.deposit_totals.charged_back,
+            ])?;
+            for (tx, authorization) in &account.authorized {
+                authorization_statement.execute(params![
+                    sql_id(account.client),
+                    sql_id(*tx),
+                    authorization.amount.to_string(),
+                    authorization.captured.to_string(),
+                    authorization_state_name(authorization.state),
+                ])?;
+            }
+            write_deposits(connection, account)?;
+        }
+        let mut tx_statement =
+            connection.prepare_cached("INSERT OR IGNORE INTO seen_ids (tx) VALUES (?1)")?;
+        for tx in tx_ids {
+            tx_statement.execute(params![sql_id(*tx)])?;
+        }
+        Ok(())
+    }
+
+    // Accounts with their authorizations and the transaction IDs saved by earlier runs. Deposits are read when a row needs them.
+    pub fn load<A: Amount>(&self) -> Result<Saved<A>, EngineError> {
+        let mut accounts = HashMap::new();
+        let mut statement = self
+            .connection
+            .prepare("SELECT client, available, held, pending, total, locked, disputed_total, pending_total, chargebacked_count FROM accounts")?;
+        let rows = statement.query_map([], |row| {
+            let mut account = Account::<A>::new(read_id(row, 0)?);
+            account.available = read_amount(row, 1)?;
+            account.held = read_amount(row, 2)?;
+            account.pending = read_amount(row, 3)?;
+            account.total = read_amount(row, 4)?;
+            account.locked = row.get(5)?;
+            account.deposit_totals = DepositTotals {
+                disputed: read_amount(row, 6)?,
+                pending: read_amount(row, 7)?,
+                charged_back: row.get(8)?,
+            };
+            Ok(account)
+        })?;
+        for account in rows {
+            let account = account?;
+            accounts.insert(account.client, account);
+        }
+        let mut statement = self
+            .connection
+            .prepare("SELECT client, tx, amount, captured, state FROM authorizations")?;
+        let rows = statement.query_map([], |row| {
+            let state: String = row.get(4)?;
+            let state =
+                parse_authorization_state(&state).ok_or_else(|| unknown_state(4, &state))?;
+            Ok((
+                read_id::<ClientId>(row, 0)?,
+                read_id::<TxId>(row, 1)?,
+                Authorization {
+                    amount: read_amount(row, 2)?,
+                    captured: read_amount(row, 3)?,
+                    state,
+                },
+            ))
+        })?;
+        for authorization in rows {
+            let (client, tx, authorization) = authorization?;
+            accounts
+                .entry(client)
+                .or_insert_with(|| Account::new(client))
+                .authorized
+                .insert(tx, authorization);
+        }
+        let mut statement = self.connection.prepare("SELECT tx FROM seen_ids")?;
+        let tx_ids = statement
+            .query_map([], |row| read_id::<TxId>(row, 0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok((accounts, tx_ids))
+    }
+}
+
+impl<A: Amount> DepositStore<A> for SqliteDatabase {
+    fn evict(
+        &mut self,
+        accounts: &mut [&mut Account<A>],
+        tx_ids: &[TxId],
+    ) -> Result<(), EngineError> {
+        if self.uncommitted == 0 {
+            self.connection.execute_batch("BEGIN")?;
+        }
+        // A row that fails to be written is undone. The rows before it are still committed.
+        self.connection.execute_batch("SAVEPOINT row")?;
+        if let Err(error) = self.write(accounts, tx_ids) {
+            self.connection
+                .execute_batch("ROLLBACK TO row; RELEASE row; COMMIT")?;
+            self.uncommitted = 0;
+            return Err(error);
+        }
+        self.connection.execute_batch("RELEASE row")?;
+        self.uncommitted += 1;
+        if self.uncommitted >= self.commit_rows {
+            self.commit()?;
+        }
+        for account in accounts.iter_mut() {
+            account.deposited.clear();
+        }
+        Ok(())
+    }
+
+    fn deposit(&self, client: ClientId, tx: TxId) -> Result<Option<Deposit<A>>, EngineError> {
+        read_stored_deposit(&self.connection, client, tx)
+    }
+
+    fn deposits(&self, client: ClientId) -> Result<Vec<(TxId, Deposit<A>)>, EngineError> {
+        read_stored_deposits(&self.connection, client)
+    }
+
+    fn flush(&mut self) -> Result<(), EngineError> {
+        self.commit()
+    }
+}
+
+// Commits the rows written since the last commit, e.g. when the engine is used row by row through `Engine::apply`.
+impl Drop for SqliteDatabase {
+    fn drop(&mut self) {
+        if let Err(error) = self.commit() {
+            error!(
+                "The last rows could not be saved to the database: {}.",
+                error
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::AuditMode;
+    use crate::generate::{Generator, GeneratorConfig};
+    use crate::{Engine, EngineConfig, Rejection, Settlement, Transaction, TransactionType};
+    use pretty_assertions::assert_eq;
+    use rust_decimal_macros::dec;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    // Counts the reads of every deposit of a client.
+    #[derive(Debug)]
+    struct CountingDeposits {
+        store: SqliteDeposits,
+        full_reads: Rc<Cell<usize>>,
+    }
+
+    impl<A: Amount> DepositStore<A> for CountingDeposits {
+        fn evict(
+            &mut self,
+            accounts: &mut [&mut Account<A>],
+            tx_ids: &[TxId],
+        ) -> Result<(), EngineError> {
+            self.store.evict(accounts, tx_ids)
+        }
+
+        fn deposit(&self, client: ClientId, tx: TxId) -> Result<Option<Deposit<A>>, EngineError> {
+            self.store.deposit(client, tx)
+        }
+
+        fn deposits(&self, client: ClientId) -> Result<Vec<(TxId, Deposit<A>)>, EngineError> {
+            self.full_reads.set(self.full_reads.get() + 1);
+            self.store.deposits(client)
+        }
+    }
+
+    #[test]
+    fn test_sqlite_deposits() -> Result<(), EngineError> {
+        let mut generated = Vec::new();
+        Generator::new(GeneratorConfig {
+            seed: 5,
+            dispute_rate: 0.1,
+            close_rate: 0.1,
+            ..GeneratorConfig::default()
+        })
+        .write_csv(&mut generated)?;
+        for (input, config) in [
+            (generated, EngineConfig::default()),
+            (
+                std::fs::read("test_dispute_lifecycle.csv")?,
+                EngineConfig {
+                    unlock_on_representment: true,
+                    audit: Some(AuditMode::EveryTransaction),
+                    ..EngineConfig::default()
+                },
+            ),
+            (
+                std::fs::read("test_settlement.csv")?,
+                EngineConfig {
+                    settlement: Settlement::Delayed(2),
+                    ..EngineConfig::default()
+                },
+            ),
+            (std::fs::read("test_batches.csv")?, EngineConfig::default()),
+        ] {
+            let mut in_memory = Engine::new(config.clone());
+            in_memory.process_records(input.as_slice())?;
+            let full_reads = Rc::new(Cell::new(0));
+            let mut on_disk = Engine::with_deposit_store(
+                config,
+                Box::new(CountingDeposits {
+                    store: SqliteDeposits::temporary()?,
+                    full_reads: full_reads.clone(),
+                }),
+            );
+            on_disk.process_records(input.as_slice())?;
+            // Only the balances are kept in memory, and a row only reads the deposit it references,
+            // even when auditing every transaction and unlocking on representment.
+            assert!(on_disk
+                .accounts()
+                .values()
+                .all(|account| account.deposits().next().is_none()));
+            assert_eq!(full_reads.get(), 0);
+            on_disk.load_deposits()?;
+            assert_eq!(on_disk.accounts(), in_memory.accounts());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_sqlite_deposits_file() -> Result<(), EngineError> {
+        let path =
+            std::env::temp_dir().join(format!("payment_engine_store_{}.db", std::process::id()));
+        let mut engine = Engine::<Decimal>::with_database(
+            EngineConfig::default(),
+            SqliteDatabase::open(&path)?,
+        )?;
+        engine.process_records("type,client,tx,amount\ndeposit,1,1,5\n".as_bytes())?;
+        drop(engine);
+
+        // An existing file, here a database, is refused and keeps its deposits.
+        match SqliteDeposits::create(&path) {
+            Err(EngineError::StoreExists(refused)) => {
+                assert_eq!(refused, path.display().to_string())
+            }
+            other => panic!("expected StoreExists, got {:?}", other),
+        }
+        let database = SqliteDatabase::open(&path)?;
+        assert!(DepositStore::<Decimal>::deposit(&database, 1, 1)?.is_some());
+        drop(database);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+
+        // A file created by the store is deleted with it, so the next run can create it again.
+        let store = SqliteDeposits::create(&path)?;
+        assert!(path.exists());
+        drop(store);
+        assert!(!path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_sqlite_database() -> Result<(), EngineError> {
+        let path = std::env::temp_dir().join(format!("payment_engine_{}.db", std::process::id()));
+        let input = std::fs::read_to_string("test_process_records.csv")?;
+        let lines: Vec<&str> = input.lines().collect();
+        let (first, second) = lines[1..].split_at(lines.len() / 2);
+        let mut in_memory = Engine::new(EngineConfig::default());
+        in_memory.process_records(input.as_bytes())?;
+
+        // Two runs on the same database end with the same accounts as one run over the whole input.
+        for rows in [first, second] {
+            let part = format!("{}\n{}\n", lines[0], rows.join("\n"));
+            let mut engine = Engine::<Decimal>::with_database(
+                EngineConfig::default(),
+                SqliteDatabase::open(&path)?,
+            )?;
+            engine.process_records(part.as_bytes())?;
+        }
+        let mut resumed =
+            Engine::with_database(EngineConfig::default(), SqliteDatabase::open(&path)?)?;
+        resumed.load_deposits()?;
+        assert_eq!(resumed.accounts(), in_memory.accounts());
+
+        // Transaction IDs of earlier runs are still known, and a rolled back batch leaves the database untouched.
+        let rejection = resumed.apply_batch(&[
+            Transaction::new(TransactionType::Deposit, 9, 100, Some(dec!(5))),
+            Transaction::new(TransactionType::Deposit, 9, 1, Some(dec!(5))),
+        ]);
+        assert_eq!(rejection.unwrap_err().rejection, Rejection::DuplicateTx);
+        drop(resumed);
+        let (accounts, tx_ids) = SqliteDatabase::open(&path)?.load::<Decimal>()?;
+        assert!(!accounts.contains_key(&9));
+        assert!(!tx_ids.contains(&100));
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_sqlite_commit_rows() -> Result<(), EngineError> {
+        let path =
+            std::env::temp_dir().join(format!("payment_engine_commit_{}.db", std::process::id()));
+        let saved_tx_ids = || -> Result<usize, EngineError> {
+            Ok(SqliteDatabase::open(&path)?.load::<Decimal>()?.1.len())
+        };
+        let mut engine = Engine::<Decimal>::with_database(
+            EngineConfig::default(),
+            SqliteDatabase::open(&path)?.commit_rows(3),
+        )?;
+        for tx in 1..=4 {
+            assert_eq!(
+                engine.apply(&Transaction::new(
+                    TransactionType::Deposit,
+                    1,
+                    tx,
+                    Some(dec!(1))
+                )),
+                Ok(())
+            );
+        }
+        // The fourth row waits for the next commit.
+        assert_eq!(saved_tx_ids()?, 3);
+        engine.process_records("type,client,tx,amount\ndeposit,1,5,1\n".as_bytes())?;
+        // The rest is committed once the input is processed.
+        assert_eq!(saved_tx_ids()?, 5);
+        assert_eq!(
+            engine.apply(&Transaction::new(
+                TransactionType::Deposit,
+                1,
+                6,
+                Some(dec!(1))
+            )),
+            Ok(())
+        );
+        drop(engine);
+        assert_eq!(saved_tx_ids()?, 6);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_sqlite_crash() -> Result<(), EngineError> {
+        let path =
+            std::env::temp_dir().join(format!("payment_engine_crash_{}.db", std::process::id()));
+        let rows = [
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(dec!(10))),
+            Transaction::new(TransactionType::Withdrawal, 1, 2, Some(dec!(4))),
+            Transaction::new(TransactionType::Dispute, 1, 1, None),
+        ];
+        let mut engine = Engine::<Decimal>::with_database(
+            EngineConfig::default(),
+            SqliteDatabase::open(&path)?,
+        )?;
+        for row in &rows {
+            assert_eq!(engine.apply(row), Ok(()));
+        }
+        // The process stops without flushing or dropping the engine: every applied row is already saved.
+        std::mem::forget(engine);
+        let (accounts, mut tx_ids) = SqliteDatabase::open(&path)?.load::<Decimal>()?;
+        tx_ids.sort_unstable();
+        assert_eq!(tx_ids, vec![1, 2]);
+        assert_eq!(accounts[&1].available(), dec!(-4));
+        assert_eq!(accounts[&1].held(), dec!(10));
+
+        // A new run resumes from the saved state.
+        let mut resumed = Engine::<Decimal>::with_database(
+            EngineConfig::default(),
+            SqliteDatabase::open(&path)?,
+        )?;
+        assert_eq!(resumed.apply(&rows[0]), Err(Rejection::DuplicateTx));
+        assert_eq!(
+            resumed.apply(&Transaction::new(TransactionType::Resolve, 1, 1, None)),
+            Ok(())
+        );
+        assert_eq!(resumed.accounts()[&1].available(), dec!(6));
+        drop(resumed);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_sql_id_order() -> Result<(), EngineError> {
+        let connection = Connection::open_in_memory()?;
+        connection.execute_batch("CREATE TABLE ids (id INTEGER PRIMARY KEY)")?;
+        let ids = [u64::MAX - 1, 7, u64::MAX, 0];
+        for id in ids {
+            connection.execute("INSERT INTO ids (id) VALUES (?1)", params![sql_id(id)])?;
+        }
+        let mut statement = connection.prepare("SELECT id FROM ids ORDER BY id")?;
+        let mut read = statement
+            .query_map([], |row| read_id::<u64>(row, 0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        // SQLite orders the IDs above i64::MAX first.
+        assert_eq!(read, vec![u64::MAX - 1, u64::MAX, 0, 7]);
+        // Every ID is read back unchanged, so they sort in order once read.
+        read.sort_unstable();
+        assert_eq!(read, vec![0, 7, u64::MAX - 1, u64::MAX]);
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "wide-ids")]
+    fn test_sqlite_wide_ids() -> Result<(), EngineError> {
+        let path =
+            std::env::temp_dir().join(format!("payment_engine_wide_{}.db", std::process::id()));
+        let max = u64::MAX;
+        let runs = [
+            format!(
+                "type,client,tx,amount\n\
+                 deposit,{max},{max},5\n\
+                 authorize,{max},{},2\n",
+                max - 1
+            ),
+            // IDs above i64::MAX are read back unchanged: the deposit can be disputed and its ID is still known.
+            format!(
+                "type,client,tx,amount\n\
+                 deposit,{max},{max},5\n\
+                 dispute,{max},{max},\n"
+            ),
+        ];
+        for run in &runs {
+            let mut engine = Engine::<Decimal>::with_database(
+                EngineConfig::default(),
+                SqliteDatabase::open(&path)?,
+            )?;
+            engine.process_records(run.as_bytes())?;
+        }
+        let (accounts, tx_ids) = SqliteDatabase::open(&path)?.load::<Decimal>()?;
+        let account = &accounts[&max];
+        // The disputed deposit and the authorization are both held.
+        assert_eq!(account.held(), dec!(7));
+        assert!(account.authorized.contains_key(&(max - 1)));
+        assert!(tx_ids.contains(&max));
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+        Ok(())
+    }
+}